@@ -0,0 +1,8 @@
+ALTER TABLE blocks
+ADD COLUMN status TEXT NOT NULL DEFAULT 'pending';
+
+CREATE INDEX blocks_number_idx ON blocks (number);
+-- the status refresh only goes through the blocks that aren't final yet
+CREATE INDEX blocks_unfinalized_idx ON blocks (number) WHERE status <> 'finalized';
+
+COMMENT ON COLUMN blocks.status IS 'pending -> confirmed (`CONFIRMATIONS` deep or at/below the `safe` head) -> finalized (at/below the `finalized` head). Only finalized blocks can no longer be reorged out.';
@@ -0,0 +1,10 @@
+ALTER TABLE txs
+ADD COLUMN effective_gas_price NUMERIC,
+ADD COLUMN gas_used NUMERIC,
+ADD COLUMN fee_wei NUMERIC,
+ADD COLUMN base_fee_per_gas NUMERIC,
+ADD COLUMN priority_fee_per_gas NUMERIC;
+
+COMMENT ON COLUMN txs.fee_wei IS 'effective_gas_price * gas_used, kept so fee_usdt can be recomputed with a corrected price';
+COMMENT ON COLUMN txs.base_fee_per_gas IS 'Base fee of the including block, NULL for pre-London blocks';
+COMMENT ON COLUMN txs.priority_fee_per_gas IS 'effective_gas_price - base_fee_per_gas, NULL for pre-London blocks';
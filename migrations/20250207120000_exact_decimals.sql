@@ -0,0 +1,6 @@
+-- prices & fees are kept as exact decimals, floats can't be reconciled against exchange statements
+ALTER TABLE blocks
+ALTER COLUMN eth_usdt TYPE NUMERIC;
+
+ALTER TABLE txs
+ALTER COLUMN fee_usdt TYPE NUMERIC;
@@ -0,0 +1,10 @@
+ALTER TABLE blocks
+ADD COLUMN base_fee_per_gas NUMERIC;
+
+ALTER TABLE txs
+ADD COLUMN burnt_fee_wei NUMERIC,
+ADD COLUMN priority_fee_wei NUMERIC;
+
+COMMENT ON COLUMN blocks.base_fee_per_gas IS 'EIP-1559 base fee, NULL for pre-London blocks';
+COMMENT ON COLUMN txs.burnt_fee_wei IS 'base_fee_per_gas * gas_used, the part of the fee that gets burned';
+COMMENT ON COLUMN txs.priority_fee_wei IS 'priority_fee_per_gas * gas_used, the part of the fee that goes to the block builder';
@@ -0,0 +1,12 @@
+ALTER TABLE blocks
+ADD COLUMN blob_gas_used NUMERIC,
+ADD COLUMN excess_blob_gas NUMERIC;
+
+ALTER TABLE txs
+ADD COLUMN blob_gas_used NUMERIC,
+ADD COLUMN blob_gas_price NUMERIC,
+ADD COLUMN blob_fee_wei NUMERIC;
+
+COMMENT ON COLUMN blocks.blob_gas_used IS 'EIP-4844 blob gas used by the block, NULL for pre-Cancun blocks';
+COMMENT ON COLUMN txs.blob_fee_wei IS 'blob_gas_used * blob_gas_price, only set for type-3 (blob) txs. Included in fee_wei & fee_usdt';
+COMMENT ON COLUMN txs.fee_wei IS 'effective_gas_price * gas_used (+ blob_fee_wei for blob txs), kept so fee_usdt can be recomputed with a corrected price';
@@ -0,0 +1,16 @@
+ALTER TABLE txs
+ADD COLUMN pool_address TEXT,
+ADD COLUMN price_pair TEXT;
+
+CREATE INDEX txs_pool_address_idx ON txs (LOWER(pool_address));
+
+COMMENT ON COLUMN txs.pool_address IS 'The tracked pool the tx interacted with (the first one, if it interacted with several)';
+COMMENT ON COLUMN txs.price_pair IS 'The pair fee_usdt got priced with, see block_prices';
+
+-- every pool can be priced with its own pair, blocks.eth_usdt keeps the price of the default pair
+CREATE TABLE block_prices (
+    block_hash TEXT NOT NULL REFERENCES blocks (hash) ON DELETE CASCADE,
+    pair TEXT NOT NULL,
+    price NUMERIC NOT NULL,
+    PRIMARY KEY (block_hash, pair)
+);
@@ -0,0 +1,8 @@
+ALTER TABLE blocks
+ADD COLUMN price_sources TEXT[];
+
+ALTER TABLE block_prices
+ADD COLUMN sources TEXT[];
+
+COMMENT ON COLUMN blocks.price_sources IS 'The price sources eth_usdt is made of, more than one for a (median) aggregated price';
+COMMENT ON COLUMN block_prices.sources IS 'The price sources the price is made of, more than one for a (median) aggregated price';
@@ -0,0 +1,11 @@
+-- historical prices as fetched from each source, so they're only ever fetched once
+CREATE TABLE prices (
+    pair TEXT NOT NULL,
+    source TEXT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    price NUMERIC NOT NULL,
+    fetched_at TIMESTAMP DEFAULT NOW (),
+    PRIMARY KEY (pair, source, timestamp)
+);
+
+COMMENT ON COLUMN prices.timestamp IS 'The (unix) timestamp the price was requested for, i.e the block timestamp';
@@ -0,0 +1,4 @@
+ALTER TABLE batch_jobs
+ADD COLUMN price_pair TEXT;
+
+COMMENT ON COLUMN batch_jobs.price_pair IS 'The pair the job prices its blocks with, the job executor''s configured pair when not requested (filled in once the job gets picked up)';
@@ -0,0 +1,20 @@
+ALTER TABLE blocks
+ADD COLUMN price_timestamp BIGINT,
+ADD COLUMN price_gap BIGINT;
+
+ALTER TABLE block_prices
+ADD COLUMN price_timestamp BIGINT,
+ADD COLUMN price_gap BIGINT;
+
+-- a block in a candle gap is priced differently by each strategy & max gap, the cache is kept per staleness
+ALTER TABLE prices
+ADD COLUMN price_timestamp BIGINT,
+ADD COLUMN staleness TEXT NOT NULL DEFAULT '',
+DROP CONSTRAINT prices_pkey,
+ADD PRIMARY KEY (pair, source, staleness, timestamp);
+
+COMMENT ON COLUMN blocks.price_timestamp IS 'When eth_usdt was observed (e.g the open time of its candle), NULL for on-chain & spot prices';
+COMMENT ON COLUMN blocks.price_gap IS 'price_timestamp - the block timestamp in seconds, negative for a price observed before the block. Bound by PRICE_MAX_GAP';
+COMMENT ON COLUMN block_prices.price_gap IS 'price_timestamp - the block timestamp in seconds, negative for a price observed before the block';
+COMMENT ON COLUMN prices.price_timestamp IS 'When the price was observed, as opposed to the (block) timestamp it was requested for';
+COMMENT ON COLUMN prices.staleness IS 'The PRICE_STRATEGY & PRICE_MAX_GAP the price got resolved with, e.g nearest:60';
@@ -0,0 +1,7 @@
+ALTER TABLE batch_jobs
+ADD COLUMN attempts INT NOT NULL DEFAULT 0,
+ADD COLUMN error TEXT;
+
+COMMENT ON COLUMN batch_jobs.status IS 'pending -> processing -> completed. A failed attempt puts the job back to pending (to be retried after a backoff), or to failed once it''s out of retries (`JOB_MAX_RETRIES`)';
+COMMENT ON COLUMN batch_jobs.attempts IS 'How many times the job executor picked up the job';
+COMMENT ON COLUMN batch_jobs.error IS 'The error the latest failed attempt ended with, cleared once the job completes';
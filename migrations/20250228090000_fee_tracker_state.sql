@@ -0,0 +1,14 @@
+-- the fee tracker's own progress, blocks.number can't tell since the jobs store (historical) blocks too
+CREATE TABLE fee_tracker_state (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id), -- a single row
+    last_block BIGINT NOT NULL,
+    finalized_block BIGINT,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW ()
+);
+
+-- the tracker was at the highest stored block before it kept its own progress, the first backfill starts right after it
+INSERT INTO fee_tracker_state (last_block)
+SELECT MAX(number) FROM blocks HAVING MAX(number) IS NOT NULL;
+
+COMMENT ON COLUMN fee_tracker_state.last_block IS 'The highest block the fee tracker went through (live or backfilled), the next backfill starts right after it';
+COMMENT ON COLUMN fee_tracker_state.finalized_block IS 'The highest finalized head the blocks statuses got refreshed up to, the stored blocks at/below it are all finalized';
@@ -1,5 +1,15 @@
 use clap::{Parser, ValueEnum};
+use eyre::{eyre, Result};
 use secrecy::SecretString;
+use sqlx::types::BigDecimal;
+use tracing::warn;
+
+use crate::price_providers::{
+    PriceSourceKind, PriceStrategy, BINANCE_URL, COINBASE_URL, KRAKEN_URL, OKX_URL,
+};
+
+/// UniswapV3's ETH-USDC
+pub const DEFAULT_LIQUIDITY_POOL: &str = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640";
 
 #[derive(Debug, Clone, Eq, PartialEq, ValueEnum)]
 pub enum Component {
@@ -43,13 +53,153 @@ pub struct Args {
     )]
     pub components: Vec<Component>,
 
+    /// Comma separated `<address>[:<price pair>]` list, pools without a pair are priced with `PRICE_PAIR`.
+    /// Defaults to UniswapV3's ETH-USDC
+    #[arg(long, env = "LIQUIDITY_POOLS", value_delimiter = ',')]
+    pub liquidity_pools: Vec<String>,
+
+    /// Deprecated, the single pool `LIQUIDITY_POOLS` replaced
+    #[arg(long, env = "LIQUIDITY_POOL", value_delimiter = ',', hide = true)]
+    pub liquidity_pool: Vec<String>,
+
+    #[arg(long, env = "PRICE_PAIR", default_value = "ETHUSDT")]
+    pub price_pair: String,
+
+    /// Comma separated quote currencies (e.g `USD,EUR,GBP`) each block gets priced in, on top of the pools' pairs
+    #[arg(long, env = "QUOTE_CURRENCIES", value_delimiter = ',')]
+    pub quote_currencies: Vec<String>,
+
+    /// Where the blocks' prices come from, several sources get aggregated into their median
     #[arg(
         long,
-        env = "LIQUIDITY_POOL",
-        default_value = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640" // UniswapV3's ETH-USDC
+        value_enum,
+        env = "PRICE_SOURCES",
+        value_delimiter = ',',
+        default_value = "binance"
     )]
-    pub liquidity_pool: String,
+    pub price_sources: Vec<PriceSourceKind>,
 
-    #[arg(long, env = "PRICE_PAIR", default_value = "ETHUSDT")]
-    pub price_pair: String,
+    /// Relative deviation from the median past which an aggregated source's price is discarded
+    #[arg(long, env = "PRICE_MAX_DEVIATION", default_value = "0.01")]
+    pub price_max_deviation: BigDecimal,
+
+    /// Price sources tried in order when `PRICE_SOURCES` fail to price a block
+    #[arg(
+        long,
+        value_enum,
+        env = "PRICE_FALLBACK_SOURCES",
+        value_delimiter = ','
+    )]
+    pub price_fallback_sources: Vec<PriceSourceKind>,
+
+    /// Seconds each source of the fallback chain gets to answer
+    #[arg(long, env = "PRICE_SOURCE_TIMEOUT", default_value = "10")]
+    pub price_source_timeout: u64,
+
+    #[arg(long, env = "BINANCE_URL", default_value = BINANCE_URL)]
+    pub binance_url: String,
+
+    #[arg(long, env = "COINBASE_URL", default_value = COINBASE_URL)]
+    pub coinbase_url: String,
+
+    #[arg(long, env = "KRAKEN_URL", default_value = KRAKEN_URL)]
+    pub kraken_url: String,
+
+    #[arg(long, env = "OKX_URL", default_value = OKX_URL)]
+    pub okx_url: String,
+
+    /// CSV/JSON file the `fixture` price source reads its prices from
+    #[arg(long, env = "PRICE_FIXTURE")]
+    pub price_fixture: Option<String>,
+
+    /// What a block with no candle at its timestamp (e.g during a venue's maintenance) gets priced with
+    #[arg(long, value_enum, env = "PRICE_STRATEGY", default_value = "nearest")]
+    pub price_strategy: PriceStrategy,
+
+    /// Seconds between a block and the candle(s) it can be priced with
+    #[arg(long, env = "PRICE_MAX_GAP", default_value = "60")]
+    pub price_max_gap: i64,
+
+    /// Number of blocks on top of a block before it's considered confirmed
+    #[arg(long, env = "CONFIRMATIONS", default_value = "12")]
+    pub confirmations: u64,
+
+    /// Most blocks a single `eth_getLogs` call spans, halved whenever the RPC rejects the range
+    #[arg(long, env = "LOGS_BLOCK_RANGE", default_value = "2000")]
+    pub logs_block_range: u64,
+
+    /// Receipt/block RPC calls a job keeps in flight at once
+    #[arg(long, env = "RPC_CONCURRENCY", default_value = "16")]
+    pub rpc_concurrency: usize,
+
+    /// Times a failed job is retried before it's marked as failed
+    #[arg(long, env = "JOB_MAX_RETRIES", default_value = "3")]
+    pub job_max_retries: u32,
+
+    /// Seconds before a failed job's first retry, doubled on every one after it
+    #[arg(long, env = "JOB_RETRY_BACKOFF", default_value = "30")]
+    pub job_retry_backoff: u64,
+
+    /// Seconds a job executor that stopped refreshing its heartbeat (e.g crashed) keeps its jobs, before they're requeued
+    #[arg(long, env = "JOB_HEARTBEAT_TTL", default_value = "30")]
+    pub job_heartbeat_ttl: u64,
+}
+
+impl Args {
+    /// The pools to track, the deprecated `LIQUIDITY_POOL` is still honored when `LIQUIDITY_POOLS` isn't set
+    pub fn pools(&self) -> Result<Vec<String>> {
+        match (
+            self.liquidity_pools.is_empty(),
+            self.liquidity_pool.is_empty(),
+        ) {
+            (false, false) => Err(eyre!(
+                "Both LIQUIDITY_POOLS and the deprecated LIQUIDITY_POOL are set, only keep LIQUIDITY_POOLS"
+            )),
+            (false, true) => Ok(self.liquidity_pools.clone()),
+            (true, false) => {
+                warn!("LIQUIDITY_POOL is deprecated, use LIQUIDITY_POOLS instead");
+                Ok(self.liquidity_pool.clone())
+            }
+            (true, true) => Ok(vec![DEFAULT_LIQUIDITY_POOL.to_string()]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pools() {
+        let pool = "0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8";
+        let cases = vec![
+            // (LIQUIDITY_POOLS, LIQUIDITY_POOL, expected)
+            (vec![], vec![], Some(vec![DEFAULT_LIQUIDITY_POOL])),
+            (vec![pool], vec![], Some(vec![pool])),
+            (vec![], vec![pool], Some(vec![pool])), // deprecated
+            (vec![pool], vec![pool], None),
+        ];
+
+        // set on the parsed args, so the pools in the environment don't leak in
+        let mut args = Args::try_parse_from(["tx-fees"]).unwrap();
+        for (pools, deprecated, expected) in cases {
+            args.liquidity_pools = pools.iter().map(|p| p.to_string()).collect();
+            args.liquidity_pool = deprecated.iter().map(|p| p.to_string()).collect();
+            match expected {
+                Some(expected) => assert_eq!(
+                    args.pools().unwrap(),
+                    expected,
+                    "Failed for {:?}, {:?}",
+                    pools,
+                    deprecated
+                ),
+                None => assert!(
+                    args.pools().is_err(),
+                    "Expected an error for {:?}, {:?}",
+                    pools,
+                    deprecated
+                ),
+            }
+        }
+    }
 }
@@ -10,19 +10,26 @@ use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
     components::api::{
-        fees::{__path_get_tx_fee, get_tx_fee, TxFee},
+        fees::{__path_get_tx_fee, get_tx_fee, BlockStatus, CurrencyFee, FeeBreakdown, TxFee},
         jobs::{
             __path_create_batch_job, __path_get_job_status, create_batch_job, get_job_status,
             BatchJobRequest, BatchJobResponse,
         },
     },
-    configs::ServerConfig,
+    configs::{ServerConfig, SupportedPairs},
 };
 
 #[derive(OpenApi)]
 #[openapi(
     paths(get_tx_fee, create_batch_job, get_job_status,),
-    components(schemas(TxFee, BatchJobRequest, BatchJobResponse))
+    components(schemas(
+        TxFee,
+        BlockStatus,
+        FeeBreakdown,
+        CurrencyFee,
+        BatchJobRequest,
+        BatchJobResponse
+    ))
 )]
 struct ApiDoc;
 
@@ -35,7 +42,12 @@ impl ServerApp {
     pub async fn build(config: ServerConfig) -> eyre::Result<Self> {
         let listener = TcpListener::bind(format!("{}:{}", config.host, config.port))?;
         let port = listener.local_addr().unwrap().port();
-        let server = start_server(listener, config.db_pool, config.redis_client)?;
+        let server = start_server(
+            listener,
+            config.db_pool,
+            config.redis_client,
+            config.supported_pairs,
+        )?;
 
         Ok(Self { port, server })
     }
@@ -55,11 +67,13 @@ fn start_server(
     listener: TcpListener,
     db_pool: PgPool,
     redis_client: redis::Client,
+    supported_pairs: SupportedPairs,
 ) -> std::result::Result<Server, std::io::Error> {
     let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(redis_client.clone()))
+            .app_data(web::Data::new(supported_pairs.clone()))
             // used to check the healthiness of the Server,
             // for example by load balancers
             .route(
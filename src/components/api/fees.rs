@@ -1,24 +1,175 @@
 use actix_web::{web, HttpResponse};
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sqlx::types::BigDecimal;
 use tracing::{error, info};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{configs::currency_pair, helpers::wei_to_eth};
 
 #[derive(Serialize, ToSchema)]
 #[schema(example = json!({
     "tx_hash": "0x05f23901ca4a9f69e3ff0af3dec39f2876000974fc9d64f53897bf5ac5e3e700",
     "block_hash": "0x...",
     "block_number": 12345,
-    "fee_usdt": 1.23,
-    "eth_usdt_ratio": 1800.0
+    "pool_address": "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640",
+    "price_pair": "ETHUSDT",
+    "fee_quote": "1.23",
+    "eth_quote_ratio": "1800",
+    "price_sources": ["binance", "coinbase", "okx"],
+    "status": "finalized",
+    "effective_gas_price": "25000000000",
+    "gas_used": "150000",
+    "fee_wei": "3750000000000000",
+    "base_fee_per_gas": "24000000000",
+    "priority_fee_per_gas": "1000000000",
+    "blob_gas_used": null,
+    "blob_gas_price": null,
+    "blob_fee_wei": null,
+    "block_blob_gas_used": "393216",
+    "block_excess_blob_gas": "0",
+    "breakdown": {
+        "burnt_fee_wei": "3600000000000000",
+        "priority_fee_wei": "150000000000000",
+        "blob_fee_wei": null,
+        "burnt_fee_quote": "6.48",
+        "priority_fee_quote": "0.27",
+        "blob_fee_quote": null
+    },
+    "currency_fee": {
+        "currency": "EUR",
+        "price_pair": "ETHEUR",
+        "eth_price": "1650",
+        "fee": "6.1875",
+        "price_sources": ["binance"]
+    }
 }))]
 pub struct TxFee {
     tx_hash: String,
     block_hash: String,
     block_number: i64,
-    fee_usdt: f64,
-    eth_usdt_ratio: f64,
+    // missing for txs stored before multiple pools were tracked
+    pool_address: Option<String>,
+    price_pair: Option<String>,
+    // in the quote currency of `price_pair` (e.g USDT for ETHUSDT),
+    // decimals are string encoded, so they don't lose precision in JSON
+    fee_quote: String,
+    eth_quote_ratio: String,
+    // where eth_quote_ratio came from, missing for blocks stored before it was recorded
+    price_sources: Option<Vec<String>>,
+    status: BlockStatus,
+    // raw values, missing for txs stored before they were persisted
+    effective_gas_price: Option<String>,
+    gas_used: Option<String>,
+    fee_wei: Option<String>,
+    base_fee_per_gas: Option<String>,
+    priority_fee_per_gas: Option<String>,
+    // EIP-4844, missing for non-blob txs / pre-Cancun blocks
+    blob_gas_used: Option<String>,
+    blob_gas_price: Option<String>,
+    blob_fee_wei: Option<String>,
+    block_blob_gas_used: Option<String>,
+    block_excess_blob_gas: Option<String>,
+    // missing for pre-London blocks
+    breakdown: Option<FeeBreakdown>,
+    // only set when requested through `currency`
+    currency_fee: Option<CurrencyFee>,
+}
+
+/// The fee in the requested quote currency, priced with the block's price of `ETH<currency>`
+#[derive(Serialize, ToSchema)]
+pub struct CurrencyFee {
+    currency: String,
+    price_pair: String,
+    eth_price: String,
+    fee: String,
+    price_sources: Option<Vec<String>>,
+}
+
+impl CurrencyFee {
+    fn new(
+        currency: &str,
+        fee_wei: &BigDecimal,
+        eth_price: &BigDecimal,
+        price_sources: Option<Vec<String>>,
+    ) -> Self {
+        Self {
+            currency: currency.to_uppercase(),
+            price_pair: currency_pair(currency),
+            eth_price: to_decimal_string(eth_price),
+            fee: to_decimal_string(&(wei_to_eth(fee_wei) * eth_price)),
+            price_sources,
+        }
+    }
+}
+
+/// How much of the fee got burned (base fee) versus paid to the block builder (priority fee).
+/// The blob fee of EIP-4844 txs is burned as well, but is reported separately.
+#[derive(Serialize, ToSchema)]
+pub struct FeeBreakdown {
+    burnt_fee_wei: String,
+    priority_fee_wei: String,
+    blob_fee_wei: Option<String>,
+    burnt_fee_quote: String,
+    priority_fee_quote: String,
+    blob_fee_quote: Option<String>,
+}
+
+impl FeeBreakdown {
+    fn new(
+        burnt_fee_wei: &BigDecimal,
+        priority_fee_wei: &BigDecimal,
+        blob_fee_wei: Option<&BigDecimal>,
+        eth_price: &BigDecimal,
+    ) -> Self {
+        let to_quote = |wei: &BigDecimal| to_decimal_string(&(wei_to_eth(wei) * eth_price));
+
+        Self {
+            burnt_fee_wei: to_decimal_string(burnt_fee_wei),
+            priority_fee_wei: to_decimal_string(priority_fee_wei),
+            blob_fee_wei: blob_fee_wei.map(to_decimal_string),
+            burnt_fee_quote: to_quote(burnt_fee_wei),
+            priority_fee_quote: to_quote(priority_fee_wei),
+            blob_fee_quote: blob_fee_wei.map(to_quote),
+        }
+    }
+}
+
+/// How settled the block containing the tx is. Only `finalized` blocks can't be reorged out anymore.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockStatus {
+    Pending,
+    Confirmed,
+    Finalized,
+}
+
+// NUMERIC values come back with the column's scale (i.e trailing zeros),
+// they're normalized and never rendered in scientific notation
+fn to_decimal_string(value: &BigDecimal) -> String {
+    value.normalized().to_plain_string()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TxFeeQuery {
+    /// Only return the tx if it interacted with the given pool
+    pool: Option<String>,
+    /// Also return the fee in the given quote currency (e.g EUR), one of the configured `QUOTE_CURRENCIES`
+    currency: Option<String>,
+}
+
+// used to sanity check the user pool input
+fn is_valid_address(address: &str) -> bool {
+    let re = Regex::new(r"^0x([A-Fa-f0-9]{40})$").unwrap();
+    re.is_match(address)
+}
+
+// used to sanity check the user currency input, e.g EUR
+fn is_valid_currency(currency: &str) -> bool {
+    let re = Regex::new(r"^[A-Za-z]{3,5}$").unwrap();
+    re.is_match(currency)
 }
 
 // used to sanity check the user tx_hash input
@@ -31,20 +182,23 @@ fn is_valid_tx_hash(tx_hash: &str) -> bool {
     get,
     path = "/v1/fees/{tx_hash}",
     params(
-        ("tx_hash" = String, Path, description = "Ethereum transaction hash")
+        ("tx_hash" = String, Path, description = "Ethereum transaction hash"),
+        TxFeeQuery
     ),
     responses(
         (status = 200, description = "Transaction fee details", body = TxFee),
-        (status = 400, description = "Invalid transaction hash format"),
-        (status = 404, description = "Transaction not found"),
+        (status = 400, description = "Invalid transaction hash, pool address or currency format"),
+        (status = 404, description = "Transaction not found, or not priced in the requested currency"),
         (status = 500, description = "Internal server error"),
     )
 )]
 pub async fn get_tx_fee(
     db_pool: web::Data<sqlx::PgPool>,
     tx_hash: web::Path<String>,
+    query: web::Query<TxFeeQuery>,
 ) -> HttpResponse {
     let tx_hash_str = tx_hash.into_inner();
+    let TxFeeQuery { pool, currency } = query.into_inner();
 
     // validate tx_hash format
     if !is_valid_tx_hash(&tx_hash_str) {
@@ -56,19 +210,86 @@ pub async fn get_tx_fee(
             .json(json!({"error": "Invalid transaction hash format"}));
     }
 
+    if let Some(pool) = &pool {
+        if !is_valid_address(pool) {
+            error!(pool = %pool, "Invalid pool address format received");
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Invalid pool address format"}));
+        }
+    }
+
+    if let Some(currency) = &currency {
+        if !is_valid_currency(currency) {
+            error!(currency = %currency, "Invalid currency format received");
+            return HttpResponse::BadRequest().json(json!({"error": "Invalid currency format"}));
+        }
+    }
+
     let row = sqlx::query!(
-        "SELECT t.hash as tx_hash, t.block_hash, b.number as block_number,
-                t.fee_usdt, b.eth_usdt as eth_usdt_ratio
+        r#"SELECT t.hash as tx_hash, t.block_hash, b.number as block_number,
+                t.pool_address, t.price_pair,
+                t.fee_usdt, COALESCE(bp.price, b.eth_usdt) as "eth_usdt_ratio!",
+                COALESCE(bp.sources, b.price_sources) as price_sources, b.status,
+                t.effective_gas_price, t.gas_used, t.fee_wei,
+                t.base_fee_per_gas, t.priority_fee_per_gas,
+                t.burnt_fee_wei, t.priority_fee_wei,
+                t.blob_gas_used, t.blob_gas_price, t.blob_fee_wei,
+                b.blob_gas_used as block_blob_gas_used, b.excess_blob_gas as block_excess_blob_gas,
+                cp.price as currency_price, cp.sources as currency_price_sources
          FROM txs t
          JOIN blocks b ON t.block_hash = b.hash
-         WHERE t.hash = $1",
-        tx_hash_str
+         LEFT JOIN block_prices bp ON bp.block_hash = t.block_hash AND bp.pair = t.price_pair
+         LEFT JOIN block_prices cp ON cp.block_hash = t.block_hash AND cp.pair = $3
+         WHERE t.hash = $1 AND ($2::TEXT IS NULL OR LOWER(t.pool_address) = LOWER($2))"#,
+        tx_hash_str,
+        pool,
+        currency.as_deref().map(currency_pair)
     )
     .fetch_optional(db_pool.get_ref())
     .await;
 
     match row {
         Ok(Some(r)) => {
+            let status = match r.status.as_str() {
+                "pending" => BlockStatus::Pending,
+                "confirmed" => BlockStatus::Confirmed,
+                "finalized" => BlockStatus::Finalized,
+                _ => {
+                    error!(
+                        tx_hash = %tx_hash_str,
+                        status = ?r.status,
+                        "Invalid block status in database"
+                    );
+                    return HttpResponse::InternalServerError().finish();
+                }
+            };
+
+            let breakdown = match (&r.burnt_fee_wei, &r.priority_fee_wei) {
+                (Some(burnt), Some(priority)) => Some(FeeBreakdown::new(
+                    burnt,
+                    priority,
+                    r.blob_fee_wei.as_ref(),
+                    &r.eth_usdt_ratio,
+                )),
+                _ => None,
+            };
+
+            // fees stored before the raw values were persisted can't be priced in another currency
+            let currency_fee = match (&currency, &r.fee_wei, &r.currency_price) {
+                (None, _, _) => None,
+                (Some(currency), Some(fee_wei), Some(eth_price)) => Some(CurrencyFee::new(
+                    currency,
+                    fee_wei,
+                    eth_price,
+                    r.currency_price_sources.clone(),
+                )),
+                (Some(currency), _, _) => {
+                    return HttpResponse::NotFound().json(json!({
+                        "error": format!("The transaction isn't priced in {}", currency.to_uppercase())
+                    }));
+                }
+            };
+
             info!(
                 tx_hash = %tx_hash_str,
                 block_number = r.block_number,
@@ -78,8 +299,24 @@ pub async fn get_tx_fee(
                 tx_hash: r.tx_hash,
                 block_hash: r.block_hash,
                 block_number: r.block_number,
-                fee_usdt: r.fee_usdt,
-                eth_usdt_ratio: r.eth_usdt_ratio,
+                pool_address: r.pool_address,
+                price_pair: r.price_pair,
+                fee_quote: to_decimal_string(&r.fee_usdt),
+                eth_quote_ratio: to_decimal_string(&r.eth_usdt_ratio),
+                price_sources: r.price_sources,
+                status,
+                effective_gas_price: r.effective_gas_price.as_ref().map(to_decimal_string),
+                gas_used: r.gas_used.as_ref().map(to_decimal_string),
+                fee_wei: r.fee_wei.as_ref().map(to_decimal_string),
+                base_fee_per_gas: r.base_fee_per_gas.as_ref().map(to_decimal_string),
+                priority_fee_per_gas: r.priority_fee_per_gas.as_ref().map(to_decimal_string),
+                blob_gas_used: r.blob_gas_used.as_ref().map(to_decimal_string),
+                blob_gas_price: r.blob_gas_price.as_ref().map(to_decimal_string),
+                blob_fee_wei: r.blob_fee_wei.as_ref().map(to_decimal_string),
+                block_blob_gas_used: r.block_blob_gas_used.as_ref().map(to_decimal_string),
+                block_excess_blob_gas: r.block_excess_blob_gas.as_ref().map(to_decimal_string),
+                breakdown,
+                currency_fee,
             })
         }
         Ok(None) => HttpResponse::NotFound().finish(),
@@ -97,6 +334,7 @@ pub async fn get_tx_fee(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn test_is_valid_tx_hash() {
@@ -129,4 +367,61 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_is_valid_currency() {
+        let cases = vec![
+            ("EUR", true),
+            ("usd", true),
+            ("USDT", true),
+            ("EU", false),   // too short
+            ("EUR1", false), // invalid characters
+            ("ETHEUR1", false),
+            ("", false),
+        ];
+
+        for (currency, expected) in cases {
+            assert_eq!(
+                is_valid_currency(currency),
+                expected,
+                "Failed for {}",
+                currency
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_valid_address() {
+        let cases = vec![
+            ("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640", true),
+            ("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640", true), // checksummed
+            ("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f56", false),  // too short
+            ("88e6a0c2ddd26feeb64f039a2c41296fcb3f5640", false),  // missing 0x prefix
+            ("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f564Z", false), // invalid characters
+        ];
+
+        for (address, expected) in cases {
+            assert_eq!(
+                is_valid_address(address),
+                expected,
+                "Failed for {}",
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_decimal_string() {
+        let cases = vec![
+            ("15.000464284000", "15.000464284"),
+            ("3500.00", "3500"),
+            ("100000000000000000000000", "100000000000000000000000"),
+            ("0.0000000000000000010", "0.000000000000000001"),
+        ];
+
+        for (value, expected) in cases {
+            let value = BigDecimal::from_str(value).unwrap();
+            assert_eq!(to_decimal_string(&value), expected);
+        }
+    }
 }
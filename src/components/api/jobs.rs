@@ -1,10 +1,13 @@
 use actix_web::{web, HttpResponse};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::PgPool;
 use tracing::{error, warn};
 use utoipa::ToSchema;
 
+use crate::{components::job_executor::queue::QUEUE, configs::SupportedPairs};
+
 use std::{
     fmt::Display,
     time::{SystemTime, UNIX_EPOCH},
@@ -13,11 +16,15 @@ use std::{
 #[derive(Serialize, Deserialize, ToSchema)]
 #[schema(example = json!({
     "start_time": 1514764800,
-    "end_time": 1674864000
+    "end_time": 1674864000,
+    "price_pair": "ETHUSDT"
 }))]
 pub struct BatchJobRequest {
     start_time: i64,
     end_time: i64,
+    /// The pair to price the blocks with, defaults to the job executor's configured pair.
+    /// Only prices the txs the job stores, the ones already stored (e.g by the fee tracker) keep their pair
+    price_pair: Option<String>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -31,14 +38,18 @@ pub enum BatchJobStatus {
     Pending,
     InProgress,
     Completed,
+    /// out of retries, see the job's `error`
+    Failed,
 }
 
+// the statuses as they're stored in `batch_jobs`
 impl Display for BatchJobStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BatchJobStatus::Pending => write!(f, "pending"),
-            BatchJobStatus::InProgress => write!(f, "in_progress"),
+            BatchJobStatus::InProgress => write!(f, "processing"),
             BatchJobStatus::Completed => write!(f, "completed"),
+            BatchJobStatus::Failed => write!(f, "failed"),
         }
     }
 }
@@ -58,19 +69,26 @@ fn is_valid_time_range(start_time: i64, end_time: i64) -> bool {
     start_time >= DEFI_START && end_time <= now && start_time < end_time
 }
 
+// used to sanity check the user price pair input, e.g ETHUSDT
+fn is_valid_price_pair(price_pair: &str) -> bool {
+    let re = Regex::new(r"^[A-Za-z0-9]{5,20}$").unwrap();
+    re.is_match(price_pair)
+}
+
 #[utoipa::path(
     post,
     path = "/v1/jobs",
     request_body = BatchJobRequest,
     responses(
         (status = 201, description = "Batch job created", body = BatchJobResponse),
-        (status = 400, description = "Invalid time range"),
+        (status = 400, description = "Invalid time range, or a price pair the price sources can't price"),
         (status = 500, description = "Internal server error"),
     )
 )]
 pub async fn create_batch_job(
     db_pool: web::Data<PgPool>,
     redis: web::Data<redis::Client>,
+    supported_pairs: web::Data<SupportedPairs>,
     req: web::Json<BatchJobRequest>,
 ) -> HttpResponse {
     if !is_valid_time_range(req.start_time, req.end_time) {
@@ -78,11 +96,22 @@ pub async fn create_batch_job(
             .json(json!({"error": "Invalid time range. Must be between 2018-01-01 and now"}));
     }
 
+    if let Some(price_pair) = &req.price_pair {
+        if !is_valid_price_pair(price_pair) {
+            return HttpResponse::BadRequest().json(json!({"error": "Invalid price pair"}));
+        }
+        if !supported_pairs.contains(price_pair) {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Price pair not supported by the price sources"}));
+        }
+    }
+
     let job_id = match sqlx::query!(
-        "INSERT INTO batch_jobs (start_time, end_time, status) VALUES ($1, $2, $3) RETURNING id",
+        "INSERT INTO batch_jobs (start_time, end_time, status, price_pair) VALUES ($1, $2, $3, $4) RETURNING id",
         req.start_time,
         req.end_time,
-        BatchJobStatus::Pending.to_string()
+        BatchJobStatus::Pending.to_string(),
+        req.price_pair.as_ref().map(|pair| pair.to_uppercase())
     )
     .fetch_one(db_pool.get_ref())
     .await
@@ -111,7 +140,7 @@ pub async fn create_batch_job(
     };
 
     if let Err(e) = redis::cmd("RPUSH")
-        .arg("batch_jobs")
+        .arg(QUEUE)
         .arg(job_id)
         .query_async::<()>(&mut conn)
         .await
@@ -133,6 +162,12 @@ pub struct BatchJobStatusResponse {
     status: BatchJobStatus,
     start_time: i64,
     end_time: i64,
+    // null until the job executor picks up a job that didn't request a pair
+    price_pair: Option<String>,
+    /// times the job executor picked up the job
+    attempts: i32,
+    /// the error of the latest failed attempt
+    error: Option<String>,
 }
 
 #[utoipa::path(
@@ -151,7 +186,7 @@ pub async fn get_job_status(db_pool: web::Data<PgPool>, job_id: web::Path<i64>)
     let job_id = job_id.into_inner();
 
     match sqlx::query!(
-        "SELECT id, status, start_time, end_time FROM batch_jobs WHERE id = $1",
+        "SELECT id, status, start_time, end_time, price_pair, attempts, error FROM batch_jobs WHERE id = $1",
         job_id
     )
     .fetch_optional(db_pool.get_ref())
@@ -160,8 +195,9 @@ pub async fn get_job_status(db_pool: web::Data<PgPool>, job_id: web::Path<i64>)
         Ok(Some(job)) => {
             let status = match job.status.as_str() {
                 "pending" => BatchJobStatus::Pending,
-                "in_progress" => BatchJobStatus::InProgress,
+                "processing" => BatchJobStatus::InProgress,
                 "completed" => BatchJobStatus::Completed,
+                "failed" => BatchJobStatus::Failed,
                 _ => {
                     error!(
                         job_id = job_id,
@@ -177,6 +213,9 @@ pub async fn get_job_status(db_pool: web::Data<PgPool>, job_id: web::Path<i64>)
                 status,
                 start_time: job.start_time,
                 end_time: job.end_time,
+                price_pair: job.price_pair,
+                attempts: job.attempts,
+                error: job.error,
             })
         }
         Ok(None) => {
@@ -224,4 +263,25 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_is_valid_price_pair() {
+        let cases = vec![
+            ("ETHUSDT", true),
+            ("ethusdc", true),
+            ("ETH/USDT", false),
+            ("ETH-USDT", false),
+            ("ETH", false),
+            ("", false),
+        ];
+
+        for (price_pair, expected) in cases {
+            assert_eq!(
+                is_valid_price_pair(price_pair),
+                expected,
+                "Failed for {}",
+                price_pair
+            );
+        }
+    }
 }
@@ -1,23 +1,146 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use alloy::{
-    providers::Provider,
-    rpc::types::{BlockNumberOrTag, Filter},
+    primitives::{Address, BlockHash, TxHash},
+    providers::{Provider, RootProvider},
+    pubsub::PubSubFrontend,
+    rpc::types::{
+        BlockNumberOrTag, BlockTransactionsKind, Filter, Header, Log, TransactionReceipt,
+    },
 };
-use eyre::Result;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
 use futures_util::stream::StreamExt;
-use tracing::info;
+use sqlx::PgPool;
+use tracing::{info, warn};
 
 use crate::{
-    configs::FeeTrackerConfig,
-    helpers::{calculate_tx_fee_usdt, store_block, store_tx},
-    price_providers::{get_pair_price, Binance},
+    components::job_executor::BlockRangeProcessor,
+    configs::{price_pairs, required_pairs, FeeTrackerConfig},
+    helpers::{
+        calculate_tx_fee, delete_block, delete_orphaned_blocks, latest_block_below,
+        refresh_block_statuses, store_blocks, store_tracked_block_number, store_tx,
+        tracked_block_number, BlockRecord, TxGas, TxRecord,
+    },
+    price_providers::{get_pairs_prices, PricedBlock, Quote},
 };
 
+/// How many of the stored blocks we're willing to walk back over when looking for orphans
+pub const MAX_REORG_DEPTH: usize = 64;
+
+/// Where the canonical chain's headers come from
+#[async_trait]
+pub trait CanonicalChain: Send + Sync {
+    async fn header_by_hash(&self, block_hash: BlockHash) -> Result<Header>;
+    async fn header_by_number(&self, block_number: u64) -> Result<Header>;
+}
+
+#[async_trait]
+impl CanonicalChain for RootProvider<PubSubFrontend> {
+    async fn header_by_hash(&self, block_hash: BlockHash) -> Result<Header> {
+        self.get_block_by_hash(block_hash, BlockTransactionsKind::Hashes)
+            .await?
+            .map(|block| block.header)
+            .ok_or_else(|| eyre!("Block {} not found", block_hash))
+    }
+
+    async fn header_by_number(&self, block_number: u64) -> Result<Header> {
+        self.get_block_by_number(block_number.into(), BlockTransactionsKind::Hashes)
+            .await?
+            .map(|block| block.header)
+            .ok_or_else(|| eyre!("Block {} not found", block_number))
+    }
+}
+
+/// The stored blocks that turned out to be orphaned, along with the canonical blocks replacing them
+#[derive(Debug)]
+pub struct Reorg {
+    /// hashes of the dropped orphaned blocks
+    pub orphaned: Vec<String>,
+    /// the canonical blocks at the heights the walk back went over, highest first
+    pub canonical: Vec<Header>,
+}
+
+/// Drops the stored blocks that are no longer part of the canonical chain `header` is the head of.
+///
+/// Any other stored block at the height of `header` is orphaned, then the stored blocks below it are walked back
+/// (at most `max_depth` of them) until one matches the canonical chain, dropping the orphaned ones on the way.
+pub async fn reconcile_stored_blocks(
+    db_pool: &PgPool,
+    chain: &impl CanonicalChain,
+    header: &Header,
+    max_depth: usize,
+) -> Result<Reorg> {
+    let mut orphaned =
+        delete_orphaned_blocks(db_pool, header.number as i64, &header.hash.to_string()).await?;
+    for hash in &orphaned {
+        warn!(
+            block_number = header.number,
+            orphaned_hash = %hash,
+            canonical_hash = %header.hash,
+            "reorg | replaced at the same height"
+        );
+    }
+
+    let mut canonical = vec![];
+    let mut child = header.clone();
+    for _ in 0..max_depth {
+        let Some((stored_hash, stored_number)) =
+            latest_block_below(db_pool, child.number as i64).await?
+        else {
+            break;
+        };
+
+        let parent = if stored_number as u64 + 1 == child.number {
+            if stored_hash == child.parent_hash.to_string() {
+                break;
+            }
+            chain.header_by_hash(child.parent_hash).await?
+        } else {
+            let parent = chain.header_by_number(stored_number as u64).await?;
+            if stored_hash == parent.hash.to_string() {
+                break;
+            }
+            parent
+        };
+
+        warn!(
+            block_number = stored_number,
+            orphaned_hash = %stored_hash,
+            canonical_hash = %parent.hash,
+            "reorg | parent hash mismatch"
+        );
+        orphaned.extend(
+            delete_orphaned_blocks(db_pool, stored_number, &parent.hash.to_string()).await?,
+        );
+        canonical.push(parent.clone());
+        child = parent;
+    }
+
+    Ok(Reorg {
+        orphaned,
+        canonical,
+    })
+}
+
 /*
- * Listens for new txs in the pool and calculates the fee in USDT
- * based on the effective gas price, gas used and the ETH/USDT price at the time the
- * block got confirmed. (i.e all txs in a block use the same ETH/USDT price)
+ * Listens for new txs in the pools and calculates the fee in the quote currency of the pool's pair (e.g USDT)
+ * based on the effective gas price, gas used (+ blob gas for EIP-4844 txs) and the price of the pair at the time the
+ * block got confirmed. (i.e all txs in a block use the same price per pair)
+ * A new block is stored along with its prices & the txs it got picked up with in a single transaction,
+ * the same bulk path the job executor stores its chunks with.
+ *
+ * Chain reorganizations are handled in two ways:
+ * 1. logs flagged as `removed` drop their (orphaned) block, the txs go with it through the cascade
+ * 2. each new block is checked against the stored blocks at its height and below it, any stored block that's
+ *    no longer part of the canonical chain (parent hash mismatch) is dropped and the canonical one gets re-ingested
+ *
+ * On startup, the blocks produced since the last one the tracker went through (i.e while it was down)
+ * are backfilled through the same code path the job executor uses, before switching to the live logs.
+ * The tracker keeps its own high-water mark for that, the blocks stored by the jobs don't move it.
+ *
+ * Every new block also moves the stored blocks along pending -> confirmed -> finalized,
+ * based on the configured confirmation depth and the chain's `safe`/`finalized` heads.
  *
  * The WS client is also automatically reconnecting in case of a disconnect.
  */
@@ -26,55 +149,351 @@ pub struct FeeTrackerApp;
 impl FeeTrackerApp {
     pub async fn run(config: FeeTrackerConfig) -> Result<()> {
         let filter = Filter::new()
-            .address(config.pool_address)
+            .address(
+                config
+                    .pools
+                    .iter()
+                    .map(|pool| pool.address)
+                    .collect::<Vec<_>>(),
+            )
             .from_block(BlockNumberOrTag::Latest);
 
+        // the bulk of the gap is backfilled before subscribing (so the subscription doesn't lag behind),
+        // whatever got produced in the meantime is backfilled right after, so no logs get lost in between
+        Self::backfill(&config).await?;
         let sub = config.provider.subscribe_logs(&filter).await?;
+        let backfilled_to = Self::backfill(&config).await?;
 
         let mut stream = sub.into_stream();
-        let mut seen_txs = HashSet::new();
-        let mut seen_blocks: HashMap<String, f64> = HashMap::new(); // block_hash -> eth_usdt price
+        let mut tracker = Tracker {
+            pairs: price_pairs(&config.pools, &config.price_pair, &config.currencies),
+            required_pairs: required_pairs(&config.pools, &config.price_pair),
+            config,
+            filter,
+            seen_txs: HashMap::new(),
+            seen_blocks: HashMap::new(),
+        };
 
         while let Some(log) = stream.next().await {
+            let is_backfilled = matches!(log.block_number, Some(number) if number <= backfilled_to);
+
+            if log.removed {
+                tracker.handle_removed_log(&log).await?;
+            } else if is_backfilled {
+                continue;
+            } else if let Some(tx_hash) = log.transaction_hash {
+                tracker.track_tx(tx_hash, log.address()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills the gap between the last block the tracker went through and the chain head.
+    /// Returns the chain head it backfilled up to.
+    async fn backfill(config: &FeeTrackerConfig) -> Result<u64> {
+        let tracked = tracked_block_number(&config.db_pool).await?;
+        let head = config.provider.get_block_number().await?;
+
+        let Some((start_block, end_block)) = backfill_range(tracked, head) else {
+            if tracked.is_none() {
+                info!("The tracker never ran before, skipping the backfill");
+            }
+            store_tracked_block_number(&config.db_pool, head as i64).await?;
+            return Ok(head);
+        };
+
+        info!(
+            start_block = start_block,
+            end_block = end_block,
+            "Backfilling the blocks missed while the tracker was down"
+        );
+        BlockRangeProcessor {
+            provider: &config.provider,
+            db_pool: &config.db_pool,
+            pools: &config.pools,
+            price_pair: &config.price_pair,
+            currencies: &config.currencies,
+            price_source: &*config.price_source,
+            fetch: config.fetch,
+        }
+        .process(start_block, end_block)
+        .await?;
+        store_tracked_block_number(&config.db_pool, end_block as i64).await?;
+
+        Ok(end_block)
+    }
+}
+
+/// The blocks the tracker missed, from the one after the last it went through up to `head`.
+/// `None` when it's caught up with `head`, or never ran before
+fn backfill_range(tracked: Option<i64>, head: u64) -> Option<(u64, u64)> {
+    let start_block = tracked? as u64 + 1;
+    (start_block <= head).then_some((start_block, head))
+}
+
+struct Tracker {
+    config: FeeTrackerConfig,
+    filter: Filter,
+    pairs: Vec<String>,
+    /// the pairs a block can't be stored without, the quote currencies' ones are best effort
+    required_pairs: Vec<String>,
+    seen_txs: HashMap<TxHash, String>, // tx_hash -> block_hash
+    seen_blocks: HashMap<String, BlockPricing>, // block_hash -> pricing of its txs
+}
+
+/// What every tx of a block gets priced with
+#[derive(Debug, Clone)]
+struct BlockPricing {
+    prices: HashMap<String, Quote>, // pair -> price
+    base_fee_per_gas: Option<u64>,
+}
+
+impl Tracker {
+    async fn track_tx(&mut self, tx_hash: TxHash, pool_address: Address) -> Result<()> {
+        if self.seen_txs.contains_key(&tx_hash) {
+            return Ok(());
+        }
+
+        let Some(receipt) = self
+            .config
+            .provider
+            .get_transaction_receipt(tx_hash)
+            .await?
+        else {
+            return Ok(());
+        };
+        let block_hash = receipt.block_hash.expect("No block hash");
+
+        match self.seen_blocks.get(&block_hash.to_string()).cloned() {
+            // the block is already stored, only the tx is left
+            Some(pricing) => {
+                let record = self.tx_record(&receipt, pool_address, &pricing)?;
+                store_tx(
+                    &self.config.db_pool,
+                    &block_hash.to_string(),
+                    &record.pool,
+                    &record.tx,
+                    pricing.base_fee_per_gas,
+                    &record.fee,
+                )
+                .await?;
+                self.tx_stored(tx_hash, &block_hash.to_string(), &record, &pricing);
+            }
+            None => {
+                let header = self.config.provider.header_by_hash(block_hash).await?;
+                self.reconcile_reorgs(&header).await?;
+                let pricing = self.price_block(&header).await?;
+                let record = self.tx_record(&receipt, pool_address, &pricing)?;
+                self.store_new_block(&header, pricing, vec![(tx_hash, record)])
+                    .await?;
+
+                refresh_block_statuses(
+                    &self.config.provider,
+                    &self.config.db_pool,
+                    self.config.confirmations,
+                    None,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A removed log means its block is no longer part of the canonical chain.
+    /// The replacement block's logs are delivered by the subscription as regular ones.
+    async fn handle_removed_log(&mut self, log: &Log) -> Result<()> {
+        let Some(block_hash) = log.block_hash else {
+            return Ok(());
+        };
+        let block_hash = block_hash.to_string();
+
+        self.forget_block(&block_hash);
+        if delete_block(&self.config.db_pool, &block_hash).await? > 0 {
+            warn!(
+                block_hash = %block_hash,
+                block_number = ?log.block_number,
+                "reorg | dropped orphaned block"
+            );
+        }
+        Ok(())
+    }
+
+    /// Drops the stored blocks orphaned by `header` (see `reconcile_stored_blocks`),
+    /// the canonical blocks replacing them get re-ingested.
+    async fn reconcile_reorgs(&mut self, header: &Header) -> Result<()> {
+        let reorg = reconcile_stored_blocks(
+            &self.config.db_pool,
+            &self.config.provider,
+            header,
+            MAX_REORG_DEPTH,
+        )
+        .await?;
+
+        for hash in &reorg.orphaned {
+            self.forget_block(hash);
+        }
+        for canonical in &reorg.canonical {
+            if !self.seen_blocks.contains_key(&canonical.hash.to_string()) {
+                self.ingest_block(canonical).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores all the pool txs of a (canonical) block that we haven't received through the subscription
+    async fn ingest_block(&mut self, header: &Header) -> Result<()> {
+        let logs = self
+            .config
+            .provider
+            .get_logs(&self.filter.clone().at_block_hash(header.hash))
+            .await?;
+        // tx -> the (first) pool it interacted with
+        let mut tx_hashes = HashMap::new();
+        for log in &logs {
             if let Some(tx_hash) = log.transaction_hash {
-                if seen_txs.insert(tx_hash) {
-                    if let Some(receipt) = config.provider.get_transaction_receipt(tx_hash).await? {
-                        let block_hash = receipt.block_hash.expect("No block hash").to_string();
-                        let block_number = receipt.block_number.expect("No block number") as i64;
-
-                        let eth_usdt = if !seen_blocks.contains_key(&block_hash) {
-                            let price_provider = Binance::new(&config.price_pair);
-                            let price = get_pair_price(&price_provider, None).await?;
-
-                            store_block(&config.db_pool, block_number, &block_hash, price).await?;
-                            seen_blocks.insert(block_hash.clone(), price);
-
-                            price
-                        } else {
-                            *seen_blocks.get(&block_hash).unwrap()
-                        };
-
-                        let fee_usdt = calculate_tx_fee_usdt(
-                            receipt.effective_gas_price,
-                            receipt.gas_used,
-                            eth_usdt,
-                        );
-
-                        store_tx(&config.db_pool, &tx_hash.to_string(), &block_hash, fee_usdt)
-                            .await?;
-                        info!(
-                            tx_hash = ?tx_hash,
-                            eth_usdt = eth_usdt,
-                            effective_gas_price = ?receipt.effective_gas_price,
-                            gas_used = ?receipt.gas_used,
-                            fee = receipt.effective_gas_price as f64 * receipt.gas_used as f64 * 1e-18,
-                            fee_usdt = fee_usdt,
-                            "new tx |"
-                        );
-                    }
-                }
+                tx_hashes.entry(tx_hash).or_insert(log.address());
             }
         }
+        if tx_hashes.is_empty() {
+            return Ok(());
+        }
+
+        let pricing = self.price_block(header).await?;
+        let mut txs = Vec::with_capacity(tx_hashes.len());
+        for (tx_hash, pool_address) in tx_hashes {
+            if self.seen_txs.contains_key(&tx_hash) {
+                continue;
+            }
+            if let Some(receipt) = self
+                .config
+                .provider
+                .get_transaction_receipt(tx_hash)
+                .await?
+            {
+                txs.push((tx_hash, self.tx_record(&receipt, pool_address, &pricing)?));
+            }
+        }
+        self.store_new_block(header, pricing, txs).await
+    }
+
+    /// The block's (spot) prices, it's the chain head
+    async fn price_block(&self, header: &Header) -> Result<BlockPricing> {
+        let prices = get_pairs_prices(
+            &*self.config.price_source,
+            &self.pairs,
+            &self.required_pairs,
+            PricedBlock {
+                number: header.number,
+                timestamp: None,
+            },
+        )
+        .await?;
+
+        Ok(BlockPricing {
+            prices,
+            base_fee_per_gas: header.base_fee_per_gas,
+        })
+    }
+
+    /// The receipt's tx, priced with the pair of the pool it interacted with
+    fn tx_record(
+        &self,
+        receipt: &TransactionReceipt,
+        pool_address: Address,
+        pricing: &BlockPricing,
+    ) -> Result<TxRecord> {
+        let pool = self
+            .config
+            .pools
+            .iter()
+            .find(|pool| pool.address == pool_address)
+            .ok_or_else(|| eyre!("Log from an untracked pool {}", pool_address))?;
+        let tx = TxGas::from(receipt);
+        let fee = calculate_tx_fee(&tx, &pricing.prices[&pool.price_pair].price);
+
+        Ok(TxRecord {
+            pool: pool.clone(),
+            tx,
+            fee,
+        })
+    }
+
+    /// Stores the block along with its prices & txs, in a single transaction (see `store_blocks`)
+    async fn store_new_block(
+        &mut self,
+        header: &Header,
+        pricing: BlockPricing,
+        txs: Vec<(TxHash, TxRecord)>,
+    ) -> Result<()> {
+        let block_hash = header.hash.to_string();
+        let record = BlockRecord {
+            header: header.clone(),
+            prices: pricing.prices.clone(),
+            txs: txs.iter().map(|(_, record)| record.clone()).collect(),
+        };
+        store_blocks(&self.config.db_pool, &self.config.price_pair, &[record]).await?;
+        store_tracked_block_number(&self.config.db_pool, header.number as i64).await?;
+
+        for (tx_hash, record) in &txs {
+            self.tx_stored(*tx_hash, &block_hash, record, &pricing);
+        }
+        self.seen_blocks.insert(block_hash, pricing);
         Ok(())
     }
+
+    fn tx_stored(
+        &mut self,
+        tx_hash: TxHash,
+        block_hash: &str,
+        record: &TxRecord,
+        pricing: &BlockPricing,
+    ) {
+        self.seen_txs.insert(tx_hash, block_hash.to_string());
+        let tx = &record.tx;
+        info!(
+            tx_hash = %tx.hash,
+            pool = %record.pool.address,
+            price_pair = %record.pool.price_pair,
+            eth_price = %pricing.prices[&record.pool.price_pair].price,
+            effective_gas_price = ?tx.effective_gas_price,
+            gas_used = ?tx.gas_used,
+            fee_wei = %tx.fee_wei(),
+            fee = %record.fee,
+            "new tx |"
+        );
+    }
+
+    /// Drops the in-memory state of an orphaned block, so its txs can be picked up again
+    /// once they're included in a canonical block
+    fn forget_block(&mut self, block_hash: &str) {
+        self.seen_blocks.remove(block_hash);
+        self.seen_txs.retain(|_, tx_block| tx_block != block_hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backfill_range() {
+        let cases = vec![
+            // (tracked, head, expected)
+            (Some(21_000_000), 21_000_100, Some((21_000_001, 21_000_100))),
+            (Some(21_000_099), 21_000_100, Some((21_000_100, 21_000_100))),
+            (Some(21_000_100), 21_000_100, None), // caught up
+            (Some(21_000_200), 21_000_100, None), // ahead of a lagging RPC
+            (None, 21_000_100, None),             // never ran
+        ];
+
+        for (tracked, head, expected) in cases {
+            assert_eq!(
+                backfill_range(tracked, head),
+                expected,
+                "Failed for tracked={:?}, head={}",
+                tracked,
+                head
+            );
+        }
+    }
 }
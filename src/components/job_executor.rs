@@ -1,20 +1,33 @@
-use std::collections::{HashMap, HashSet};
+pub mod queue;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use alloy::{
     eips::BlockId,
+    primitives::{Address, TxHash},
     providers::{Provider, RootProvider},
     pubsub::PubSubFrontend,
-    rpc::types::{BlockTransactionsKind, Filter},
+    rpc::types::{BlockTransactionsKind, Filter, Header, Log, TransactionReceipt},
 };
-use eyre::Result;
-use redis::AsyncCommands;
+use eyre::{eyre, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
 
 use crate::{
-    configs::JobExecutorConfig,
-    helpers::{store_block, store_tx},
-    price_providers::{get_pair_price, Binance},
+    components::job_executor::queue::JobQueue,
+    configs::{
+        price_pairs, required_pairs, FetchConfig, JobExecutorConfig, PoolConfig, RetryConfig,
+    },
+    helpers::{
+        calculate_tx_fee, refresh_block_statuses, store_blocks, BlockRecord, TxGas, TxRecord,
+    },
+    price_providers::{get_pairs_prices, PriceSource, PricedBlock},
 };
 
 /// Find the closest block to the given `target_timestamp`,
@@ -123,58 +136,383 @@ async fn find_closest_block(
     }
 }
 
-/*
- * Get all events in the given block range
- * 1. receives a filter with start and end block numbers, and the pool address
- * 2. retrieves all logs in the range
- * 3. filters unique transactions
- * 4. retrieves transaction receipts
- * 5. groups transactions by block number
- * 6. retrieves block details
- * 7. calculates transaction fees
- *
- * there's quite a lot of room for improvement here, for example
- * - we can first check if a similar job has been processed before
- * - we can batch process transactions in a single block
- * - when the range is too large, we can split it into smaller chunks because the provider might not return all logs at once
- * - we can bulk insert the data into the database
- */
-async fn get_events_range(
-    provider: &RootProvider<PubSubFrontend>,
-    filter: Filter,
-) -> Result<HashMap<(u64, i64), Vec<(String, u128, u64)>>> {
-    info!("Starting get_events_range with filter: {:?}", filter);
-    let mut events_by_block = HashMap::new();
-
-    let logs = provider.get_logs(&filter).await?;
-    info!("Received {} logs from filter", logs.len());
-
-    let unique_txs: HashSet<_> = logs.iter().filter_map(|log| log.transaction_hash).collect();
-    info!("Processing {} unique transactions", unique_txs.len());
-
-    for tx_hash in unique_txs {
-        let receipt = provider.get_transaction_receipt(tx_hash).await?.unwrap();
-        let block_num = receipt.block_number.unwrap();
-        events_by_block
-            .entry(block_num)
-            .or_insert_with(Vec::new)
-            .push((
-                tx_hash.to_string(),
-                receipt.effective_gas_price,
-                receipt.gas_used,
-            ));
+/// Markers of the errors RPCs reject a too wide `eth_getLogs` range with,
+/// e.g `query returned more than 10000 results`, `block range is too wide`, `Log response size exceeded`.
+/// Specific enough not to take a rate limit (`429 Too Many Requests`) for one
+const LOGS_RANGE_ERRORS: &[&str] = &[
+    "returned more than",
+    "more than 10000 results",
+    "too many results",
+    "max results",
+    "block range",
+    "range too",
+    "range is too",
+    "response size",
+    "limited to a",
+];
+
+/// Whether the RPC rejected an `eth_getLogs` call because of its range (rather than e.g a dropped connection)
+fn is_logs_range_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    LOGS_RANGE_ERRORS
+        .iter()
+        .any(|marker| message.contains(marker))
+}
+
+/// Walks `[start, end]` in `eth_getLogs` sized chunks. A chunk the RPC rejects gets halved,
+/// each accepted one doubles the next, up to `max_size` blocks.
+#[derive(Debug, PartialEq)]
+struct LogChunks {
+    next: u64,
+    end: u64,
+    size: u64,
+    max_size: u64,
+}
+
+impl LogChunks {
+    fn new(start: u64, end: u64, max_size: u64) -> Self {
+        let max_size = max_size.max(1);
+        Self {
+            next: start,
+            end,
+            size: max_size,
+            max_size,
+        }
+    }
+
+    /// The block range to fetch the logs of next, `None` once the whole range is walked
+    fn current(&self) -> Option<(u64, u64)> {
+        (self.next <= self.end).then(|| {
+            (
+                self.next,
+                self.end.min(self.next.saturating_add(self.size - 1)),
+            )
+        })
     }
 
-    let mut final_events = HashMap::new();
-    for (block_num, txs) in events_by_block {
-        let block = provider
-            .get_block(block_num.into(), BlockTransactionsKind::Hashes)
+    /// Moves past the current chunk, growing the next one
+    fn advance(&mut self) {
+        if let Some((_, to)) = self.current() {
+            self.next = to.saturating_add(1);
+        }
+        self.size = self.size.saturating_mul(2).min(self.max_size);
+    }
+
+    /// Halves the current chunk, `false` if it's down to a single block already
+    fn shrink(&mut self) -> bool {
+        if self.size == 1 {
+            return false;
+        }
+        self.size /= 2;
+        true
+    }
+}
+
+/// Markers of the errors a node rejects a method it doesn't implement with,
+/// e.g `the method eth_getBlockReceipts does not exist/is not available`
+const UNSUPPORTED_METHOD_ERRORS: &[&str] = &[
+    "-32601",
+    "method not found",
+    "does not exist",
+    "not supported",
+    "unsupported",
+];
+
+/// Whether the node rejected a call because it doesn't implement the method
+fn is_unsupported_method_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    UNSUPPORTED_METHOD_ERRORS
+        .iter()
+        .any(|marker| message.contains(marker))
+}
+
+/// The pool txs of a block, along with the block itself
+#[derive(Debug)]
+struct BlockEvents {
+    header: Header,
+    txs: Vec<(Address, TxGas)>, // (pool, tx)
+}
+
+/// Fetches the blocks & receipts of the logs' txs, `concurrency` RPC calls at a time.
+/// The receipts come a block at a time (`eth_getBlockReceipts`), unless the node doesn't support it.
+struct EventsFetcher<'a> {
+    provider: &'a RootProvider<PubSubFrontend>,
+    concurrency: usize,
+    /// cleared once the node turns out not to support `eth_getBlockReceipts`
+    block_receipts: AtomicBool,
+}
+
+impl<'a> EventsFetcher<'a> {
+    fn new(provider: &'a RootProvider<PubSubFrontend>, concurrency: usize) -> Self {
+        Self {
+            provider,
+            concurrency: concurrency.max(1),
+            block_receipts: AtomicBool::new(true),
+        }
+    }
+
+    /*
+     * Get all events of the given logs
+     * 1. groups the unique transactions by block number
+     * 2. retrieves the block headers
+     * 3. retrieves the transaction receipts
+     *
+     * there's quite a lot of room for improvement here, for example
+     * - we can first check if a similar job has been processed before
+     */
+    async fn get_events(&self, logs: &[Log]) -> Result<Vec<BlockEvents>> {
+        // block -> tx -> the (first) pool it interacted with
+        let mut txs_by_block: BTreeMap<u64, HashMap<TxHash, Address>> = BTreeMap::new();
+        for log in logs {
+            if let (Some(block_num), Some(tx_hash)) = (log.block_number, log.transaction_hash) {
+                txs_by_block
+                    .entry(block_num)
+                    .or_default()
+                    .entry(tx_hash)
+                    .or_insert(log.address());
+            }
+        }
+        info!(
+            "Processing {} unique transactions in {} blocks",
+            txs_by_block.values().map(HashMap::len).sum::<usize>(),
+            txs_by_block.len()
+        );
+
+        // the streams go over owned items, borrowed ones make the spawned components' futures not `Send`
+        let block_nums = txs_by_block.keys().copied().collect::<Vec<_>>();
+        let headers: Vec<Header> = stream::iter(block_nums)
+            .map(|block_num| self.get_header(block_num))
+            .buffered(self.concurrency)
+            .try_collect()
+            .await?;
+        let mut receipts = self.get_receipts(&txs_by_block).await?;
+
+        let mut events = Vec::with_capacity(headers.len());
+        for (header, txs) in headers.into_iter().zip(txs_by_block.into_values()) {
+            let mut txs = txs
+                .into_iter()
+                .map(|(tx_hash, pool_address)| {
+                    let receipt = receipts
+                        .remove(&tx_hash)
+                        .ok_or_else(|| eyre!("Receipt of {} not found", tx_hash))?;
+                    Ok((
+                        receipt.transaction_index,
+                        pool_address,
+                        TxGas::from(&receipt),
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // in the block's order rather than the map's, so the same logs always give the same events
+            txs.sort_by_key(|(index, _, _)| *index);
+            events.push(BlockEvents {
+                header,
+                txs: txs
+                    .into_iter()
+                    .map(|(_, pool_address, tx)| (pool_address, tx))
+                    .collect(),
+            });
+        }
+
+        Ok(events)
+    }
+
+    async fn get_header(&self, block_num: u64) -> Result<Header> {
+        self.provider
+            .get_block(BlockId::number(block_num), BlockTransactionsKind::Hashes)
             .await?
-            .unwrap();
-        final_events.insert((block_num, block.header.timestamp as i64), txs);
+            .map(|block| block.header)
+            .ok_or_else(|| eyre!("Block {} not found", block_num))
+    }
+
+    /// The receipts of the txs, a block at a time when the node supports it, tx by tx otherwise
+    async fn get_receipts(
+        &self,
+        txs_by_block: &BTreeMap<u64, HashMap<TxHash, Address>>,
+    ) -> Result<HashMap<TxHash, TransactionReceipt>> {
+        if self.block_receipts.load(Ordering::Relaxed) {
+            match self.get_block_receipts(txs_by_block).await {
+                Ok(receipts) => return Ok(receipts),
+                Err(err) if is_unsupported_method_error(&err.to_string()) => {
+                    warn!(error = %err, "eth_getBlockReceipts unsupported, fetching the receipts tx by tx |");
+                    self.block_receipts.store(false, Ordering::Relaxed);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        let tx_hashes = txs_by_block
+            .values()
+            .flat_map(|txs| txs.keys().copied())
+            .collect::<Vec<_>>();
+        stream::iter(tx_hashes)
+            .map(|tx_hash| async move {
+                let receipt = self
+                    .provider
+                    .get_transaction_receipt(tx_hash)
+                    .await?
+                    .ok_or_else(|| eyre!("Receipt of {} not found", tx_hash))?;
+                Ok::<_, eyre::Report>((tx_hash, receipt))
+            })
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await
+    }
+
+    async fn get_block_receipts(
+        &self,
+        txs_by_block: &BTreeMap<u64, HashMap<TxHash, Address>>,
+    ) -> Result<HashMap<TxHash, TransactionReceipt>> {
+        let block_nums = txs_by_block.keys().copied().collect::<Vec<_>>();
+        let blocks: Vec<(u64, Vec<TransactionReceipt>)> = stream::iter(block_nums)
+            .map(|block_num| async move {
+                let receipts = self
+                    .provider
+                    .get_block_receipts(BlockId::number(block_num))
+                    .await?
+                    .ok_or_else(|| eyre!("Receipts of block {} not found", block_num))?;
+                Ok::<_, eyre::Report>((block_num, receipts))
+            })
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+
+        // only the pool txs are kept, not the whole blocks'
+        Ok(blocks
+            .into_iter()
+            .flat_map(|(block_num, receipts)| {
+                let txs = &txs_by_block[&block_num];
+                receipts
+                    .into_iter()
+                    .filter(|receipt| txs.contains_key(&receipt.transaction_hash))
+            })
+            .map(|receipt| (receipt.transaction_hash, receipt))
+            .collect())
     }
+}
 
-    Ok(final_events)
+/// Retrieves all the pools' events of a block range, prices each block at its timestamp
+/// (for every pair the pools are priced with, and in every quote currency) and stores the blocks along with their txs.
+///
+/// Shared by the job executor and the fee tracker's gap backfill.
+pub(crate) struct BlockRangeProcessor<'a> {
+    pub provider: &'a RootProvider<PubSubFrontend>,
+    pub db_pool: &'a PgPool,
+    pub pools: &'a [PoolConfig],
+    pub price_pair: &'a str,
+    pub currencies: &'a [String],
+    pub price_source: &'a dyn PriceSource,
+    pub fetch: FetchConfig,
+}
+
+impl BlockRangeProcessor<'_> {
+    /// Walks `[start_block, end_block]` chunk by chunk (see `LogChunks`),
+    /// each chunk is stored before the logs of the next one get fetched
+    pub(crate) async fn process(&self, start_block: u64, end_block: u64) -> Result<()> {
+        let pairs = price_pairs(self.pools, self.price_pair, self.currencies);
+        let addresses = self
+            .pools
+            .iter()
+            .map(|pool| pool.address)
+            .collect::<Vec<_>>();
+
+        let fetcher = EventsFetcher::new(self.provider, self.fetch.concurrency);
+        let mut chunks = LogChunks::new(start_block, end_block, self.fetch.logs_block_range);
+        while let Some((from_block, to_block)) = chunks.current() {
+            let filter = Filter::new()
+                .from_block(from_block)
+                .to_block(to_block)
+                .address(addresses.clone());
+
+            let logs = match self.provider.get_logs(&filter).await {
+                Ok(logs) => logs,
+                Err(err) if is_logs_range_error(&err.to_string()) && chunks.shrink() => {
+                    warn!(
+                        from_block = from_block,
+                        to_block = to_block,
+                        error = %err,
+                        "logs range rejected, halving it |"
+                    );
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            info!(
+                from_block = from_block,
+                to_block = to_block,
+                logs = logs.len(),
+                "logs chunk |"
+            );
+
+            let events = fetcher.get_events(&logs).await?;
+            self.store_chunk(&pairs, events).await?;
+            chunks.advance();
+        }
+
+        Ok(())
+    }
+
+    async fn store_chunk(&self, pairs: &[String], events: Vec<BlockEvents>) -> Result<()> {
+        info!("Found {} blocks with events", events.len());
+
+        // the prices of the whole chunk are bulk fetched upfront, rather than block by block
+        let timestamps = events.iter().map(|block| block.header.timestamp as i64);
+        if let (Some(start_ts), Some(end_ts)) = (timestamps.clone().min(), timestamps.max()) {
+            for pair in pairs {
+                self.price_source.prefetch(pair, start_ts, end_ts).await;
+            }
+        }
+
+        let result = self.store_events(pairs, events).await;
+        self.price_source.clear_prefetched();
+        result
+    }
+
+    async fn store_events(&self, pairs: &[String], events: Vec<BlockEvents>) -> Result<()> {
+        let required = required_pairs(self.pools, self.price_pair);
+        let mut records = Vec::with_capacity(events.len());
+        for BlockEvents { header, txs } in events {
+            info!(
+                "Processing block {} with {} transactions",
+                header.number,
+                txs.len(),
+            );
+
+            let prices = get_pairs_prices(
+                self.price_source,
+                pairs,
+                &required,
+                PricedBlock {
+                    number: header.number,
+                    timestamp: Some(header.timestamp as i64),
+                },
+            )
+            .await?;
+
+            let txs = txs
+                .into_iter()
+                .map(|(pool_address, tx)| {
+                    let pool = self
+                        .pools
+                        .iter()
+                        .find(|pool| pool.address == pool_address)
+                        .ok_or_else(|| eyre!("Log from an untracked pool {}", pool_address))?;
+                    Ok(TxRecord {
+                        fee: calculate_tx_fee(&tx, &prices[&pool.price_pair].price),
+                        pool: pool.clone(),
+                        tx,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            records.push(BlockRecord {
+                header,
+                prices,
+                txs,
+            });
+        }
+
+        // the whole chunk goes in at once, in a single transaction
+        store_blocks(self.db_pool, self.price_pair, &records).await
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -185,143 +523,219 @@ struct BatchJob {
     start_block: Option<i64>,
     end_block: Option<i64>,
     status: String,
+    price_pair: Option<String>,
+}
+
+/// The pools the job prices its txs with, the ones priced with the configured pair get the job's pair instead
+fn job_pools(pools: &[PoolConfig], configured_pair: &str, job_pair: &str) -> Vec<PoolConfig> {
+    pools
+        .iter()
+        .map(|pool| PoolConfig {
+            address: pool.address,
+            price_pair: if pool.price_pair == configured_pair {
+                job_pair.to_string()
+            } else {
+                pool.price_pair.clone()
+            },
+        })
+        .collect()
 }
 
 /*
- * The main component that listens for new jobs in the Redis queue
+ * The main component that listens for new jobs in the Redis queue (see `JobQueue`)
  * and processes them one by one. Each job process goes through the following steps:
  * 1. Receive a new job from the redis queue
- * 2. Update the job status to 'processing' in the database
+ * 2. Update the job status to 'processing' in the database, along with the pair it's priced with
+ *    (the requested one, the configured one otherwise)
  * 3. Find the closest block numbers to the start and end timestamps
- * 4. Retrieve all events in the block range
+ * 4. Retrieve all events in the block range, chunk by chunk
  * 5. Calculate transaction fees for each transaction
- * 6. Store the block and transaction details in the database
+ * 6. Store the block and transaction details in the database (in bulk, a chunk at a time)
+ * 7. Refresh the confirmation status of the stored blocks
  *
+ * A job that fails at any step is put back in the queue (after a backoff) until it's out of retries,
+ * then it's marked as 'failed'. Either way, the executor moves on to the next job.
+ * A job whose executor died mid-way is put back in the queue by the other executors.
 */
 pub struct JobExecutorApp;
 impl JobExecutorApp {
     pub async fn run(config: JobExecutorConfig) -> Result<()> {
-        let mut con = config
-            .redis_client
-            .get_multiplexed_async_connection()
-            .await?;
-        let price_provider = Binance::new("ETHUSDT");
-        let provider = config.provider.clone();
+        let mut queue = JobQueue::new(config.redis_client.clone(), config.heartbeat_ttl).await?;
+        queue.spawn_maintenance(config.db_pool.clone());
 
         loop {
-            // BRPOP returns (key, value) tuple as strings
             info!("Waiting for new jobs...");
-            let result: Option<(String, String)> = con.brpop("batch_jobs", 0.0).await?;
-
-            if let Some((_, job_id_str)) = result {
-                let job_id: i64 = job_id_str.parse()?;
-
-                let job: BatchJob =
-                    sqlx::query_as!(BatchJob, "SELECT id, start_time, end_time, start_block, end_block, status FROM batch_jobs WHERE id = $1", job_id)
-                        .fetch_one(&config.db_pool)
-                        .await?;
-                info!(
-                    "Job details - start_time: {}, end_time: {}, status: {}",
-                    job.start_time, job.end_time, job.status
-                );
+            run_next(&mut queue, &config.db_pool, &config.retry, |job_id| {
+                Self::execute(&config, job_id)
+            })
+            .await?;
+        }
+    }
 
-                if job.status != "pending" {
-                    info!("Ignoring job {} with status {}", job.id, job.status);
-                    continue;
-                }
+    async fn execute(config: &JobExecutorConfig, job_id: i64) -> Result<()> {
+        let provider = &config.provider;
 
-                sqlx::query!(
-                    "UPDATE batch_jobs SET status = 'processing', updated_at = NOW() WHERE id = $1",
-                    job_id
-                )
-                .execute(&config.db_pool)
+        let job: BatchJob =
+            sqlx::query_as!(BatchJob, "SELECT id, start_time, end_time, start_block, end_block, status, price_pair FROM batch_jobs WHERE id = $1", job_id)
+                .fetch_one(&config.db_pool)
                 .await?;
-                info!("Processing job {}", job_id);
+        info!(
+            "Job details - start_time: {}, end_time: {}, status: {}",
+            job.start_time, job.end_time, job.status
+        );
 
-                let start_block = find_closest_block(&provider, job.start_time).await?;
-                let end_block = find_closest_block(&provider, job.end_time).await?;
-                info!(
-                    "Block range found - start: {}, end: {}",
-                    start_block, end_block
-                );
+        if job.status != "pending" {
+            info!("Ignoring job {} with status {}", job.id, job.status);
+            return Ok(());
+        }
 
-                sqlx::query!(
-                    "UPDATE batch_jobs SET start_block = $1, end_block = $2 WHERE id = $3",
-                    start_block as i64,
-                    end_block as i64,
-                    job_id
-                )
-                .execute(&config.db_pool)
-                .await?;
+        let price_pair = job.price_pair.unwrap_or(config.price_pair.clone());
+        let attempts = sqlx::query_scalar!(
+            "UPDATE batch_jobs SET status = 'processing', price_pair = $2, attempts = attempts + 1, updated_at = NOW() WHERE id = $1 RETURNING attempts",
+            job_id,
+            price_pair
+        )
+        .fetch_one(&config.db_pool)
+        .await?;
+        info!(
+            "Processing job {} priced with {} (attempt {})",
+            job_id, price_pair, attempts
+        );
 
-                let filter = Filter::new()
-                    .from_block(start_block)
-                    .to_block(end_block)
-                    .address(config.pool_address);
-                let events = get_events_range(&provider, filter).await?;
-                info!("Found {} blocks with events", events.len());
-
-                for ((block_num, _), txs) in events {
-                    info!(
-                        "Processing block {} with {} transactions",
-                        block_num,
-                        txs.len(),
-                    );
+        let start_block = find_closest_block(provider, job.start_time).await?;
+        let end_block = find_closest_block(provider, job.end_time).await?;
+        info!(
+            "Block range found - start: {}, end: {}",
+            start_block, end_block
+        );
 
-                    let block = provider
-                        .get_block(BlockId::number(block_num), BlockTransactionsKind::Hashes)
-                        .await?
-                        .unwrap();
-
-                    let eth_price =
-                        get_pair_price(&price_provider, Some(block.header.timestamp as i64))
-                            .await?;
-
-                    let mut tx_fees = Vec::new();
-
-                    for (tx_hash, gas_price, gas_used) in txs {
-                        let fee_eth = (gas_price as f64 * gas_used as f64) / 1e18;
-                        let fee_usdt = fee_eth * eth_price;
-
-                        tx_fees.push((tx_hash, fee_usdt));
-                    }
-
-                    store_block(
-                        &config.db_pool,
-                        block_num as i64,
-                        &block.header.hash.to_string(),
-                        eth_price,
-                    )
-                    .await?;
-
-                    for (tx_hash, fee_usdt) in tx_fees {
-                        store_tx(
-                            &config.db_pool,
-                            &tx_hash,
-                            &block.header.hash.to_string(),
-                            fee_usdt,
-                        )
-                        .await?;
-                    }
-                }
+        sqlx::query!(
+            "UPDATE batch_jobs SET start_block = $1, end_block = $2 WHERE id = $3",
+            start_block as i64,
+            end_block as i64,
+            job_id
+        )
+        .execute(&config.db_pool)
+        .await?;
+
+        BlockRangeProcessor {
+            provider,
+            db_pool: &config.db_pool,
+            pools: &job_pools(&config.pools, &config.price_pair, &price_pair),
+            price_pair: &price_pair,
+            currencies: &config.currencies,
+            price_source: &*config.price_source,
+            fetch: config.fetch,
+        }
+        .process(start_block, end_block)
+        .await?;
+
+        // the job's blocks can be below the tracker's refreshed ones, e.g historical blocks stored just now
+        refresh_block_statuses(
+            provider,
+            &config.db_pool,
+            config.confirmations,
+            Some(start_block),
+        )
+        .await?;
 
-                sqlx::query!(
-                    "UPDATE batch_jobs SET status = 'completed', updated_at = NOW() WHERE id = $1",
-                    job_id
-                )
-                .execute(&config.db_pool)
-                .await?;
+        sqlx::query!(
+            "UPDATE batch_jobs SET status = 'completed', error = NULL, updated_at = NOW() WHERE id = $1",
+            job_id
+        )
+        .execute(&config.db_pool)
+        .await?;
 
-                info!("Completed job {}", job_id);
+        info!("Completed job {}", job_id);
+        Ok(())
+    }
+}
+
+/// Runs the next job of the queue through `execute`. A failing job doesn't take the executor down,
+/// it's retried or marked as failed instead (see `handle_failure`). A failure that can't be recorded
+/// does, leaving the job in the worker's processing list for the other workers to reap
+pub async fn run_next<F, Fut>(
+    queue: &mut JobQueue,
+    db_pool: &PgPool,
+    retry: &RetryConfig,
+    execute: F,
+) -> Result<()>
+where
+    F: FnOnce(i64) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let Some(job) = queue.next().await? else {
+        return Ok(());
+    };
+
+    match job.parse::<i64>() {
+        Ok(job_id) => {
+            if let Err(err) = execute(job_id).await {
+                // only acked once the failure is recorded, i.e the job is failed or waits for its retry
+                handle_failure(db_pool, retry, queue, job_id, &err)
+                    .await
+                    .map_err(|record_err| {
+                        eyre!(
+                            "Unable to record the failure of job {} ({:#}): {:#}",
+                            job_id,
+                            err,
+                            record_err
+                        )
+                    })?;
             }
         }
+        Err(_) => warn!(job_id = %job, "Ignoring malformed job id"),
     }
+    queue.ack(&job).await
+}
+
+/// Puts the failed job back in the queue after a backoff (see `JobQueue::retry`),
+/// or marks it as failed once it's out of retries
+async fn handle_failure(
+    db_pool: &PgPool,
+    retry: &RetryConfig,
+    queue: &mut JobQueue,
+    job_id: i64,
+    err: &eyre::Report,
+) -> Result<()> {
+    let Some(attempts) =
+        sqlx::query_scalar!("SELECT attempts FROM batch_jobs WHERE id = $1", job_id)
+            .fetch_optional(db_pool)
+            .await?
+    else {
+        warn!(job_id = job_id, error = ?err, "Unknown job failed");
+        return Ok(());
+    };
+
+    let delay = retry.delay(attempts);
+    sqlx::query!(
+        "UPDATE batch_jobs SET status = $2, error = $3, updated_at = NOW() WHERE id = $1",
+        job_id,
+        if delay.is_some() { "pending" } else { "failed" },
+        format!("{:#}", err)
+    )
+    .execute(db_pool)
+    .await?;
+
+    let Some(delay) = delay else {
+        error!(job_id = job_id, attempts = attempts, error = ?err, "job failed |");
+        return Ok(());
+    };
+    warn!(
+        job_id = job_id,
+        attempts = attempts,
+        retry_in = ?delay,
+        error = ?err,
+        "job attempt failed, retrying |"
+    );
+
+    queue.retry(job_id, delay).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use alloy::{primitives::Address, providers::ProviderBuilder};
+    use alloy::providers::ProviderBuilder;
     use std::{env::var, str::FromStr};
 
     #[tokio::test]
@@ -377,12 +791,98 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_is_logs_range_error() {
+        let cases = vec![
+            ("query returned more than 10000 results", true),
+            ("block range is too wide", true),
+            ("exceed maximum block range: 5000", true),
+            ("Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range", true),
+            ("eth_getLogs is limited to a 10,000 range", true),
+            ("query exceeds max results 20000", true),
+            ("too many results, narrow the query", true),
+            ("rate limit exceeded", false),
+            ("429 Too Many Requests", false),
+            ("too many requests, more than 100 per second", false),
+            ("connection reset by peer", false),
+        ];
+
+        for (message, expected) in cases {
+            assert_eq!(
+                is_logs_range_error(message),
+                expected,
+                "Failed for {}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_unsupported_method_error() {
+        let cases = vec![
+            ("server returned an error response: error code -32601: the method eth_getBlockReceipts does not exist/is not available", true),
+            ("Method not found", true),
+            ("eth_getBlockReceipts is not supported on this network", true),
+            ("header not found", false),
+            ("connection reset by peer", false),
+        ];
+
+        for (message, expected) in cases {
+            assert_eq!(
+                is_unsupported_method_error(message),
+                expected,
+                "Failed for {}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_log_chunks() {
+        let mut chunks = LogChunks::new(100, 1_000, 400);
+        assert_eq!(chunks.current(), Some((100, 499)));
+
+        // rejected twice, then accepted chunks grow back to the max size
+        assert!(chunks.shrink());
+        assert!(chunks.shrink());
+        assert_eq!(chunks.current(), Some((100, 199)));
+        chunks.advance();
+        assert_eq!(chunks.current(), Some((200, 399)));
+        chunks.advance();
+        assert_eq!(chunks.current(), Some((400, 799)));
+        chunks.advance();
+        assert_eq!(chunks.current(), Some((800, 1_000)));
+        chunks.advance();
+        assert_eq!(chunks.current(), None);
+
+        // a single block can't be split any further
+        let mut chunks = LogChunks::new(5, 5, 0);
+        assert_eq!(chunks.current(), Some((5, 5)));
+        assert!(!chunks.shrink());
+    }
+
+    #[test]
+    fn test_job_pools() {
+        let pool = |address: Address, pair: &str| PoolConfig {
+            address,
+            price_pair: pair.to_string(),
+        };
+        let (first, second) = (Address::ZERO, Address::repeat_byte(1));
+
+        let pools = vec![pool(first, "ETHUSDT"), pool(second, "ETHEUR")];
+        assert_eq!(job_pools(&pools, "ETHUSDT", "ETHUSDT"), pools);
+        assert_eq!(
+            job_pools(&pools, "ETHUSDT", "ETHUSDC"),
+            vec![pool(first, "ETHUSDC"), pool(second, "ETHEUR")]
+        );
+    }
+
     async fn setup_provider(rpc_url: Option<&str>) -> RootProvider<PubSubFrontend> {
         let ws = if let Some(url) = rpc_url {
             alloy::providers::WsConnect::new(url)
         } else {
             alloy::providers::WsConnect::new(
-                &var("TEST_ETH_WS_RPC_URL")
+                var("TEST_ETH_WS_RPC_URL")
                     .unwrap_or_else(|_| "wss://mainnet.gateway.tenderly.co/".to_string()),
             )
         };
@@ -410,7 +910,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_get_events_range() {
+    async fn test_get_events() {
         let provider = setup_provider(None).await;
 
         let start_block = 17000000;
@@ -425,19 +925,36 @@ mod tests {
             .from_block(start_block)
             .to_block(end_block)
             .address(pool_address);
+        let logs = provider.get_logs(&filter).await.unwrap();
 
-        let events = get_events_range(&provider, filter).await.unwrap();
+        let fetcher = EventsFetcher::new(&provider, 4);
+        let events = fetcher.get_events(&logs).await.unwrap();
 
         assert!(!events.is_empty(), "Should find some events");
 
-        for ((block_num, timestamp), txs) in events {
-            assert!(block_num >= start_block && block_num <= end_block);
-            assert!(timestamp > 0, "Timestamp should be positive");
+        // the receipts fetched tx by tx match the ones fetched a block at a time
+        fetcher.block_receipts.store(false, Ordering::Relaxed);
+        let tx_by_tx = fetcher.get_events(&logs).await.unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|block| (block.header.hash, block.txs.clone()))
+                .collect::<Vec<_>>(),
+            tx_by_tx
+                .iter()
+                .map(|block| (block.header.hash, block.txs.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        for BlockEvents { header, txs } in events {
+            assert!(header.number >= start_block && header.number <= end_block);
+            assert!(header.timestamp > 0, "Timestamp should be positive");
 
-            for (tx_hash, gas_price, gas_used) in txs {
-                assert!(tx_hash.starts_with("0x") && tx_hash.len() == 66);
-                assert!(gas_price > 0, "Gas price should be non-zero");
-                assert!(gas_used > 0, "Gas used should be non-zero");
+            for (tx_pool_address, tx) in txs {
+                assert_eq!(tx_pool_address, pool_address);
+                assert!(tx.hash.starts_with("0x") && tx.hash.len() == 66);
+                assert!(tx.effective_gas_price > 0, "Gas price should be non-zero");
+                assert!(tx.gas_used > 0, "Gas used should be non-zero");
             }
         }
     }
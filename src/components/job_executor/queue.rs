@@ -0,0 +1,199 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+use redis::{aio::MultiplexedConnection, AsyncCommands, Direction};
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+/// The jobs waiting to be picked up, the API pushes the new ones
+pub const QUEUE: &str = "batch_jobs";
+/// The failed jobs waiting for their retry, scored by when they're due
+pub const DELAYED: &str = "batch_jobs:delayed";
+/// The workers that might be holding jobs
+pub const WORKERS: &str = "batch_jobs:workers";
+
+/// The jobs a worker has picked up and isn't done with yet
+pub fn processing_key(worker: &str) -> String {
+    format!("batch_jobs:processing:{}", worker)
+}
+
+/// Expires unless the worker keeps refreshing it
+pub fn heartbeat_key(worker: &str) -> String {
+    format!("batch_jobs:heartbeat:{}", worker)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/*
+ * A reliable job queue on top of Redis lists
+ * 1. a worker atomically moves the next job from the queue into its own processing list (BLMOVE),
+ *    so a picked up job is never only held in the worker's memory
+ * 2. the job leaves the processing list once the worker is done with it (completed, failed or delayed for a retry)
+ * 3. the worker keeps a heartbeat key (with a TTL) alive for as long as it runs
+ * 4. every worker also reaps the others: the jobs held by a worker whose heartbeat expired (i.e it crashed
+ *    or got killed mid-job) go back to `pending` and to the front of the queue, to be picked up again
+ * 5. the retries wait in a sorted set, and are moved back to the queue once they're due
+ *
+ * A job can end up processed twice (e.g a worker that couldn't refresh its heartbeat in time gets reaped),
+ * which is fine since storing the blocks & txs is idempotent.
+ */
+pub struct JobQueue {
+    client: redis::Client,
+    // blocks while waiting for a job, the maintenance goes through its own connection
+    con: MultiplexedConnection,
+    worker: String,
+    heartbeat_ttl: Duration,
+}
+
+impl JobQueue {
+    pub async fn new(client: redis::Client, heartbeat_ttl: Duration) -> Result<Self> {
+        let mut con = client.get_multiplexed_async_connection().await?;
+        let worker = format!(
+            "{}:{}:{}",
+            std::env::var("HOSTNAME").unwrap_or_else(|_| "job-executor".to_string()),
+            std::process::id(),
+            now()
+        );
+
+        // the heartbeat registers the worker too, so it can't be reaped before it starts
+        heartbeat(&mut con, &worker, heartbeat_ttl).await?;
+        info!(worker = %worker, "job queue worker registered |");
+
+        Ok(Self {
+            client,
+            con,
+            worker,
+            heartbeat_ttl,
+        })
+    }
+
+    pub fn worker(&self) -> &str {
+        &self.worker
+    }
+
+    /// Waits for the next job, moving it into the worker's processing list
+    pub async fn next(&mut self) -> Result<Option<String>> {
+        Ok(self
+            .con
+            .blmove(
+                QUEUE,
+                processing_key(&self.worker),
+                Direction::Left,
+                Direction::Right,
+                0.0,
+            )
+            .await?)
+    }
+
+    /// Removes a job the worker is done with from its processing list
+    pub async fn ack(&mut self, job: &str) -> Result<()> {
+        let _: () = self.con.lrem(processing_key(&self.worker), 1, job).await?;
+        Ok(())
+    }
+
+    /// Puts the job back in the queue once `delay` has passed
+    pub async fn retry(&mut self, job_id: i64, delay: Duration) -> Result<()> {
+        let _: () = self
+            .con
+            .zadd(DELAYED, job_id, now() + delay.as_secs())
+            .await?;
+        Ok(())
+    }
+
+    /// Keeps the worker's heartbeat alive, moves the due retries back to the queue
+    /// and reaps the dead workers' jobs, every third of the heartbeat's TTL
+    pub fn spawn_maintenance(&self, db_pool: PgPool) {
+        let (client, worker, ttl) = (self.client.clone(), self.worker.clone(), self.heartbeat_ttl);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval((ttl / 3).max(Duration::from_secs(1)));
+            loop {
+                interval.tick().await;
+
+                let maintained: Result<usize> = async {
+                    let mut con = client.get_multiplexed_async_connection().await?;
+                    heartbeat(&mut con, &worker, ttl).await?;
+                    promote_due(&mut con).await?;
+                    reap(&mut con, &db_pool).await
+                }
+                .await;
+                if let Err(err) = maintained {
+                    warn!(worker = %worker, error = ?err, "job queue maintenance failed |");
+                }
+            }
+        });
+    }
+}
+
+/// Refreshes the worker's heartbeat, (re-)registering it among the `WORKERS`:
+/// a worker reaped while its heartbeat lapsed (e.g a slow maintenance tick) gets reaped again once it dies
+pub async fn heartbeat(
+    con: &mut MultiplexedConnection,
+    worker: &str,
+    heartbeat_ttl: Duration,
+) -> Result<()> {
+    let _: () = con
+        .set_ex(heartbeat_key(worker), now(), heartbeat_ttl.as_secs().max(1))
+        .await?;
+    let _: () = con.sadd(WORKERS, worker).await?;
+    Ok(())
+}
+
+/// Moves the retries that are due back to the queue, returns how many got moved
+pub async fn promote_due(con: &mut MultiplexedConnection) -> Result<usize> {
+    let due: Vec<i64> = con.zrangebyscore(DELAYED, "-inf", now()).await?;
+
+    let mut promoted = 0;
+    for job_id in due {
+        // only the worker that gets to remove it requeues it
+        let removed: i64 = con.zrem(DELAYED, job_id).await?;
+        if removed > 0 {
+            let _: () = con.rpush(QUEUE, job_id).await?;
+            info!(job_id = job_id, "retrying job |");
+            promoted += 1;
+        }
+    }
+    Ok(promoted)
+}
+
+/// Requeues the jobs of the workers whose heartbeat expired, returns how many got requeued
+pub async fn reap(con: &mut MultiplexedConnection, db_pool: &PgPool) -> Result<usize> {
+    let workers: Vec<String> = con.smembers(WORKERS).await?;
+
+    let mut requeued = 0;
+    for worker in workers {
+        if con.exists(heartbeat_key(&worker)).await? {
+            continue;
+        }
+
+        // the jobs go back to pending first, a job picked up while still `processing` would be ignored
+        let processing = processing_key(&worker);
+        let jobs: Vec<String> = con.lrange(&processing, 0, -1).await?;
+        for job_id in jobs.iter().filter_map(|job| job.parse::<i64>().ok()) {
+            sqlx::query!(
+                "UPDATE batch_jobs SET status = 'pending', updated_at = NOW() WHERE id = $1 AND status = 'processing'",
+                job_id
+            )
+            .execute(db_pool)
+            .await?;
+        }
+
+        loop {
+            let job: Option<String> = con
+                .lmove(&processing, QUEUE, Direction::Right, Direction::Left)
+                .await?;
+            let Some(job) = job else {
+                break;
+            };
+            warn!(worker = %worker, job_id = %job, "requeued the job of a dead worker |");
+            requeued += 1;
+        }
+        let _: () = con.srem(WORKERS, &worker).await?;
+    }
+    Ok(requeued)
+}
@@ -1,11 +1,238 @@
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc, time::Duration};
 
 use alloy::{
     primitives::Address,
-    providers::{ProviderBuilder, RootProvider, WsConnect},
+    providers::{Provider, ProviderBuilder, RootProvider, WsConnect},
     pubsub::PubSubFrontend,
 };
-use sqlx::PgPool;
+use eyre::{eyre, Result};
+use sqlx::{types::BigDecimal, PgPool};
+
+use tracing::warn;
+
+use crate::price_providers::{
+    is_quote_currency, new_price_source, PriceSource, PriceSourceKind, PricedBlock, Staleness,
+    VenueClients, QUOTES,
+};
+
+/// A tracked liquidity pool and the pair its txs get priced with
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolConfig {
+    pub address: Address,
+    pub price_pair: String,
+}
+
+impl PoolConfig {
+    /// Parses `<address>[:<price pair>]`, pools without a pair get priced with `default_pair`
+    pub fn parse(value: &str, default_pair: &str) -> Result<Self> {
+        let (address, price_pair) = match value.split_once(':') {
+            Some((address, pair)) if !pair.is_empty() => (address, pair),
+            Some(_) => return Err(eyre!("Missing price pair in pool {}", value)),
+            None => (value, default_pair),
+        };
+
+        Ok(Self {
+            address: Address::from_str(address.trim())?,
+            price_pair: price_pair.trim().to_uppercase(),
+        })
+    }
+
+    fn parse_all(values: &[String], default_pair: &str) -> Vec<Self> {
+        values
+            .iter()
+            .map(|value| Self::parse(value, default_pair).expect("Invalid liquidity pool"))
+            .collect()
+    }
+}
+
+/// The pair ETH gets priced in a quote currency with, e.g `EUR` -> `ETHEUR`
+pub fn currency_pair(currency: &str) -> String {
+    format!("ETH{}", currency.trim().to_uppercase())
+}
+
+/// All the distinct pairs that have to be priced for each block, `default_pair` first,
+/// then the pools' pairs and the pairs of the quote `currencies`
+pub fn price_pairs(pools: &[PoolConfig], default_pair: &str, currencies: &[String]) -> Vec<String> {
+    let mut pairs = vec![default_pair.to_string()];
+    let currency_pairs = currencies.iter().map(|currency| currency_pair(currency));
+    for pair in pools
+        .iter()
+        .map(|pool| pool.price_pair.clone())
+        .chain(currency_pairs)
+    {
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    }
+    pairs
+}
+
+/// The pairs a block can't be stored without, `default_pair` first then the pools' pairs.
+/// The quote currencies' pairs on top of them (see `price_pairs`) are best effort
+pub fn required_pairs(pools: &[PoolConfig], default_pair: &str) -> Vec<String> {
+    price_pairs(pools, default_pair, &[])
+}
+
+/// Fails on a quote currency we don't know how to price ETH in, e.g a typo
+pub fn validate_currencies(currencies: &[String]) -> Result<()> {
+    match currencies
+        .iter()
+        .find(|currency| !is_quote_currency(currency))
+    {
+        Some(currency) => Err(eyre!(
+            "Unknown quote currency {}, QUOTE_CURRENCIES has to be among {}",
+            currency,
+            QUOTES.join(",")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Spot prices the quote currencies' pairs, warning about the ones the price source can't price
+/// (e.g `ETHUSD` isn't listed by Binance), the blocks get stored without them
+async fn check_currencies(
+    price_source: &dyn PriceSource,
+    provider: &RootProvider<PubSubFrontend>,
+    currencies: &[String],
+) {
+    let Ok(number) = provider.get_block_number().await else {
+        return;
+    };
+    for currency in currencies {
+        let pair = currency_pair(currency);
+        let block = PricedBlock {
+            number,
+            timestamp: None,
+        };
+        if let Err(err) = price_source.get_price(&pair, block).await {
+            warn!(
+                currency = %currency,
+                pair = %pair,
+                error = %err,
+                "quote currency can't be priced, the blocks won't be priced in it |"
+            );
+        }
+    }
+}
+
+/// Builds the price source of a component from the `required_pairs` only, so a quote currency
+/// the source isn't able to price (e.g no tracked pool or Chainlink feed for it) doesn't fail the startup
+async fn build_price_source(
+    config: &PriceSourceConfig,
+    db_pool: &PgPool,
+    provider: &RootProvider<PubSubFrontend>,
+    pools: &[PoolConfig],
+    price_pair: &str,
+    currencies: &[String],
+) -> Result<Arc<dyn PriceSource>> {
+    let price_source = new_price_source(
+        config,
+        db_pool,
+        provider,
+        &required_pairs(pools, price_pair),
+        pools,
+    )
+    .await?;
+    check_currencies(&*price_source, provider, currencies).await;
+    Ok(price_source)
+}
+
+/// Where the blocks get priced from
+#[derive(Debug, Clone)]
+pub struct PriceSourceConfig {
+    /// more than one source gets aggregated into their median
+    pub sources: Vec<PriceSourceKind>,
+    /// relative deviation from the median past which an aggregated source's price is discarded
+    pub max_deviation: BigDecimal,
+    /// tried in order when `sources` fail to price a block
+    pub fallbacks: Vec<PriceSourceKind>,
+    /// how long each source of the fallback chain gets to answer
+    pub timeout: Duration,
+    // the venues' base URLs, e.g to go through a proxy or a mock
+    pub binance_url: String,
+    pub coinbase_url: String,
+    pub kraken_url: String,
+    pub okx_url: String,
+    /// shared by the components' price sources, so they're within each venue's rate limit together
+    pub clients: VenueClients,
+    /// file the fixture source reads its prices from
+    pub fixture: Option<String>,
+    /// what the blocks with no candle at their timestamp get priced with
+    pub staleness: Staleness,
+}
+
+impl PriceSourceConfig {
+    /// The pairs the configured sources can price the jobs with, see [`SupportedPairs`]
+    pub fn supported_pairs(&self, pools: &[String], price_pair: &str) -> SupportedPairs {
+        let pools = PoolConfig::parse_all(pools, price_pair);
+        SupportedPairs::new(
+            self.sources
+                .iter()
+                .chain(&self.fallbacks)
+                .copied()
+                .collect(),
+            required_pairs(&pools, price_pair),
+        )
+    }
+}
+
+/// The pairs a job can request, i.e the ones at least one of the price sources (or fallbacks) can price.
+/// The on-chain & fixture sources only price the pairs they got configured with
+#[derive(Debug, Clone)]
+pub struct SupportedPairs {
+    sources: Vec<PriceSourceKind>,
+    /// the pairs the sources get built with, see `required_pairs`
+    pairs: Vec<String>,
+}
+
+impl SupportedPairs {
+    pub fn new(sources: Vec<PriceSourceKind>, pairs: Vec<String>) -> Self {
+        Self { sources, pairs }
+    }
+
+    pub fn contains(&self, pair: &str) -> bool {
+        let pair = pair.to_uppercase();
+        self.sources
+            .iter()
+            .any(|kind| kind.can_price(&pair, &self.pairs))
+    }
+}
+
+/// How block ranges get fetched from the RPC
+#[derive(Debug, Clone, Copy)]
+pub struct FetchConfig {
+    /// the most blocks a single `eth_getLogs` call spans, halved whenever the RPC rejects a range
+    pub logs_block_range: u64,
+    /// how many receipt/block calls are in flight at once
+    pub concurrency: usize,
+}
+
+/// The longest a failed job waits before it's retried
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// How the failed jobs get retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    /// retries on top of the first attempt, before a job is marked as `failed`
+    pub max_retries: u32,
+    /// delay before the first retry, doubled on every one after it
+    pub backoff: Duration,
+}
+
+impl RetryConfig {
+    /// The delay before retrying a job that failed its `attempts`th attempt, `None` once it's out of retries
+    pub fn delay(&self, attempts: i32) -> Option<Duration> {
+        if attempts > self.max_retries as i32 {
+            return None;
+        }
+        let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+        Some(
+            self.backoff
+                .saturating_mul(2u32.pow(exponent))
+                .min(MAX_RETRY_BACKOFF),
+        )
+    }
+}
 
 #[derive(Debug)]
 pub struct FeeTrackerConfig {
@@ -13,8 +240,15 @@ pub struct FeeTrackerConfig {
     pub db_pool: PgPool,
     pub provider: RootProvider<PubSubFrontend>,
 
-    pub pool_address: Address,
+    pub pools: Vec<PoolConfig>,
+    /// the pair each block's `eth_usdt` is priced with
     pub price_pair: String,
+    /// the currencies each block gets priced in on top of the pools' pairs (see `block_prices`)
+    pub currencies: Vec<String>,
+    pub price_source: Arc<dyn PriceSource>,
+    /// blocks on top of a block before it's marked as `confirmed`
+    pub confirmations: u64,
+    pub fetch: FetchConfig,
     // memory management
     //pub max_seen_txs: usize,
     //pub max_seen_blocks: usize,
@@ -22,22 +256,42 @@ pub struct FeeTrackerConfig {
 }
 
 impl FeeTrackerConfig {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         db_pool: PgPool,
         rpc_url: String,
-        pool_address: String,
+        pools: Vec<String>,
         price_pair: String,
+        currencies: Vec<String>,
+        price_source: PriceSourceConfig,
+        confirmations: u64,
+        fetch: FetchConfig,
     ) -> Self {
         let provider = ProviderBuilder::new()
             .on_ws(WsConnect::new(rpc_url))
             .await
             .expect("Unable to initialise WS Provider");
+        let pools = PoolConfig::parse_all(&pools, &price_pair);
+        let price_source = build_price_source(
+            &price_source,
+            &db_pool,
+            &provider,
+            &pools,
+            &price_pair,
+            &currencies,
+        )
+        .await
+        .expect("Unable to initialise the price source");
 
         Self {
             db_pool,
             provider,
-            pool_address: Address::from_str(&pool_address).expect("Invalid pool address"),
+            pools,
             price_pair,
+            currencies,
+            price_source,
+            confirmations,
+            fetch,
         }
     }
 }
@@ -48,22 +302,50 @@ pub struct JobExecutorConfig {
     pub provider: RootProvider<PubSubFrontend>,
     pub redis_client: redis::Client,
 
-    pub pool_address: Address,
+    pub pools: Vec<PoolConfig>,
+    /// the pair each block's `eth_usdt` is priced with
     pub price_pair: String,
+    /// the currencies each block gets priced in on top of the pools' pairs (see `block_prices`)
+    pub currencies: Vec<String>,
+    pub price_source: Arc<dyn PriceSource>,
+    /// blocks on top of a block before it's marked as `confirmed`
+    pub confirmations: u64,
+    pub fetch: FetchConfig,
+    pub retry: RetryConfig,
+    /// how long an executor that stopped refreshing its heartbeat keeps its jobs, before they're requeued
+    pub heartbeat_ttl: Duration,
 }
 
 impl JobExecutorConfig {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         db_pool: PgPool,
         rpc_url: String,
         redis_url: String,
-        pool_address: String,
+        pools: Vec<String>,
         price_pair: String,
+        currencies: Vec<String>,
+        price_source: PriceSourceConfig,
+        confirmations: u64,
+        fetch: FetchConfig,
+        retry: RetryConfig,
+        heartbeat_ttl: Duration,
     ) -> Self {
         let provider = ProviderBuilder::new()
             .on_ws(WsConnect::new(rpc_url))
             .await
             .expect("Unable to initialise WS Provider");
+        let pools = PoolConfig::parse_all(&pools, &price_pair);
+        let price_source = build_price_source(
+            &price_source,
+            &db_pool,
+            &provider,
+            &pools,
+            &price_pair,
+            &currencies,
+        )
+        .await
+        .expect("Unable to initialise the price source");
 
         let redis_client = redis::Client::open(redis_url).expect("Failed to create Redis client");
 
@@ -71,8 +353,14 @@ impl JobExecutorConfig {
             db_pool,
             provider,
             redis_client,
-            pool_address: Address::from_str(&pool_address).expect("Invalid pool address"),
+            pools,
             price_pair,
+            currencies,
+            price_source,
+            confirmations,
+            fetch,
+            retry,
+            heartbeat_ttl,
         }
     }
 }
@@ -81,6 +369,8 @@ impl JobExecutorConfig {
 pub struct ServerConfig {
     pub db_pool: PgPool,
     pub redis_client: redis::Client,
+    /// the pairs the jobs can be priced with
+    pub supported_pairs: SupportedPairs,
 
     /// The host to bind the API to
     pub host: String,
@@ -89,14 +379,251 @@ pub struct ServerConfig {
 }
 
 impl ServerConfig {
-    pub fn new(db_pool: PgPool, redis_url: String, host: String, port: u16) -> Self {
+    pub fn new(
+        db_pool: PgPool,
+        redis_url: String,
+        supported_pairs: SupportedPairs,
+        host: String,
+        port: u16,
+    ) -> Self {
         let redis_client = redis::Client::open(redis_url).expect("Failed to create Redis client");
 
         Self {
             db_pool,
             redis_client,
+            supported_pairs,
             host,
             port,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_providers::PriceStrategy;
+
+    #[test]
+    fn test_pool_config_parse() {
+        let address = Address::from_str("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap();
+
+        let cases = vec![
+            (
+                "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640",
+                Some("ETHUSDT"),
+            ), // default pair
+            (
+                "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640:ETHUSDC",
+                Some("ETHUSDC"),
+            ),
+            (
+                "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640:ethusdc",
+                Some("ETHUSDC"),
+            ),
+            ("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640:", None), // missing pair
+            ("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f56:ETHUSDC", None), // invalid address
+        ];
+
+        for (value, expected_pair) in cases {
+            let result = PoolConfig::parse(value, "ETHUSDT");
+            match expected_pair {
+                Some(pair) => assert_eq!(
+                    result.unwrap(),
+                    PoolConfig {
+                        address,
+                        price_pair: pair.to_string()
+                    },
+                    "Failed for {}",
+                    value
+                ),
+                None => assert!(result.is_err(), "Expected an error for {}", value),
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let retry = RetryConfig {
+            max_retries: 3,
+            backoff: Duration::from_secs(30),
+        };
+
+        let cases = vec![
+            (0, Some(30)),
+            (1, Some(30)),
+            (2, Some(60)),
+            (3, Some(120)),
+            (4, None), // out of retries
+        ];
+
+        for (attempts, expected) in cases {
+            assert_eq!(
+                retry.delay(attempts),
+                expected.map(Duration::from_secs),
+                "Failed for {}",
+                attempts
+            );
+        }
+
+        let retry = RetryConfig {
+            max_retries: 100,
+            backoff: Duration::from_secs(30),
+        };
+        assert_eq!(retry.delay(99), Some(MAX_RETRY_BACKOFF));
+    }
+
+    #[test]
+    fn test_supported_pairs() {
+        let pairs = |pairs: &[&str]| {
+            pairs
+                .iter()
+                .map(|pair| pair.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let cases = vec![
+            (vec![PriceSourceKind::Binance], "ethusdc", true),
+            (vec![PriceSourceKind::UniswapV3], "ETHUSDC", true),
+            (vec![PriceSourceKind::UniswapV3], "ETHEUR", false),
+            (vec![PriceSourceKind::Chainlink], "ETHUSDT", false),
+            (
+                vec![PriceSourceKind::UniswapV3, PriceSourceKind::Okx],
+                "ETHEUR",
+                true,
+            ), // priced by one of them
+        ];
+
+        for (sources, pair, expected) in cases {
+            let supported = SupportedPairs::new(sources, pairs(&["ETHUSD", "ETHUSDC"]));
+            assert_eq!(supported.contains(pair), expected, "Failed for {}", pair);
+        }
+    }
+
+    #[test]
+    fn test_validate_currencies() {
+        let cases = vec![
+            (vec![], true),
+            (vec!["EUR", "usdc", " BTC"], true),
+            (vec!["EUR", "EURO"], false), // a typo
+            (vec!["ETH"], false),
+        ];
+
+        for (currencies, expected) in cases {
+            let currencies = currencies
+                .iter()
+                .map(|currency| currency.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                validate_currencies(&currencies).is_ok(),
+                expected,
+                "Failed for {:?}",
+                currencies
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_price_source_extra_currency() {
+        let provider = ProviderBuilder::new()
+            .on_ws(WsConnect::new(
+                std::env::var("TEST_ETH_WS_RPC_URL")
+                    .unwrap_or_else(|_| "wss://mainnet.gateway.tenderly.co/".to_string()),
+            ))
+            .await
+            .unwrap();
+        // the spot prices don't go through the cache, i.e the DB
+        let db_pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let config = |kind| PriceSourceConfig {
+            sources: vec![kind],
+            max_deviation: BigDecimal::from_str("0.05").unwrap(),
+            fallbacks: vec![],
+            timeout: Duration::from_secs(5),
+            binance_url: String::new(),
+            coinbase_url: String::new(),
+            kraken_url: String::new(),
+            okx_url: String::new(),
+            clients: VenueClients::default(),
+            fixture: None,
+            staleness: Staleness {
+                strategy: PriceStrategy::Fail,
+                max_gap: 0,
+            },
+        };
+        let number = provider.get_block_number().await.unwrap();
+        let block = PricedBlock {
+            number,
+            timestamp: None,
+        };
+
+        let cases = vec![
+            (
+                PriceSourceKind::UniswapV3,
+                Some("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640"),
+                "ETHUSDC",
+            ),
+            (PriceSourceKind::Chainlink, None, "ETHUSD"),
+        ];
+
+        for (kind, pool, price_pair) in cases {
+            let pools = PoolConfig::parse_all(
+                &pool.map(|pool| vec![pool.to_string()]).unwrap_or_default(),
+                price_pair,
+            );
+            let price_source = build_price_source(
+                &config(kind),
+                &db_pool,
+                &provider,
+                &pools,
+                price_pair,
+                &["EUR".to_string()],
+            )
+            .await
+            .unwrap_or_else(|err| panic!("Failed for {:?}: {}", kind, err));
+
+            assert!(
+                price_source.get_price(price_pair, block).await.is_ok(),
+                "Failed for {:?}",
+                kind
+            );
+            // the quote currency is left to the best effort pricing of the blocks
+            assert!(
+                price_source.get_price("ETHEUR", block).await.is_err(),
+                "Failed for {:?}",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn test_price_pairs() {
+        let pool = |pair: &str| PoolConfig {
+            address: Address::ZERO,
+            price_pair: pair.to_string(),
+        };
+
+        let currencies = |currencies: &[&str]| {
+            currencies
+                .iter()
+                .map(|currency| currency.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(price_pairs(&[], "ETHUSDT", &[]), vec!["ETHUSDT"]);
+        assert_eq!(
+            price_pairs(
+                &[pool("ETHUSDC"), pool("ETHUSDT"), pool("ETHUSDC")],
+                "ETHUSDT",
+                &[]
+            ),
+            vec!["ETHUSDT", "ETHUSDC"]
+        );
+        assert_eq!(
+            price_pairs(
+                &[pool("ETHUSDC")],
+                "ETHUSDT",
+                &currencies(&["usd", "EUR", "USDC"])
+            ),
+            vec!["ETHUSDT", "ETHUSDC", "ETHUSD", "ETHEUR"]
+        );
+    }
+}
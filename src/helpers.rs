@@ -1,92 +1,591 @@
-use eyre::Result;
-use sqlx::PgPool;
+use std::collections::HashMap;
 
-pub async fn store_tx(pool: &PgPool, tx_hash: &str, block_hash: &str, fee_usdt: f64) -> Result<()> {
+use alloy::{
+    providers::{Provider, RootProvider},
+    pubsub::PubSubFrontend,
+    rpc::types::{BlockNumberOrTag, BlockTransactionsKind, Header, TransactionReceipt},
+};
+use eyre::{eyre, Result};
+use sqlx::{types::BigDecimal, PgConnection, PgPool, Postgres, QueryBuilder};
+
+use crate::{configs::PoolConfig, price_providers::Quote};
+
+/// Gas values of a tx, as reported by its receipt
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxGas {
+    pub hash: String,
+    pub effective_gas_price: u128,
+    pub gas_used: u64,
+    // only set for EIP-4844 (type-3) txs
+    pub blob_gas_used: Option<u64>,
+    pub blob_gas_price: Option<u128>,
+}
+
+impl TxGas {
+    /// The total fee paid by the tx in wei, blob fees included
+    pub fn fee_wei(&self) -> BigDecimal {
+        let execution_fee =
+            BigDecimal::from(self.effective_gas_price) * BigDecimal::from(self.gas_used);
+
+        match self.blob_fee_wei() {
+            Some(blob_fee) => execution_fee + blob_fee,
+            None => execution_fee,
+        }
+    }
+
+    /// The fee paid for the blob gas in wei, `None` for non-blob txs
+    pub fn blob_fee_wei(&self) -> Option<BigDecimal> {
+        match (self.blob_gas_used, self.blob_gas_price) {
+            (Some(used), Some(price)) => Some(BigDecimal::from(used) * BigDecimal::from(price)),
+            _ => None,
+        }
+    }
+
+    /// The part of the gas price that goes to the block builder, `None` for pre-London blocks
+    pub fn priority_fee_per_gas(&self, base_fee_per_gas: Option<u64>) -> Option<u128> {
+        base_fee_per_gas.map(|base_fee| self.effective_gas_price.saturating_sub(base_fee as u128))
+    }
+
+    /// The part of the fee that gets burned in wei, `None` for pre-London blocks
+    pub fn burnt_fee_wei(&self, base_fee_per_gas: Option<u64>) -> Option<BigDecimal> {
+        base_fee_per_gas
+            .map(|base_fee| BigDecimal::from(base_fee) * BigDecimal::from(self.gas_used))
+    }
+
+    /// The part of the fee that goes to the block builder in wei, `None` for pre-London blocks
+    pub fn priority_fee_wei(&self, base_fee_per_gas: Option<u64>) -> Option<BigDecimal> {
+        self.priority_fee_per_gas(base_fee_per_gas)
+            .map(|priority_fee| BigDecimal::from(priority_fee) * BigDecimal::from(self.gas_used))
+    }
+}
+
+impl From<&TransactionReceipt> for TxGas {
+    fn from(receipt: &TransactionReceipt) -> Self {
+        Self {
+            hash: receipt.transaction_hash.to_string(),
+            effective_gas_price: receipt.effective_gas_price,
+            gas_used: receipt.gas_used,
+            blob_gas_used: receipt.blob_gas_used,
+            blob_gas_price: receipt.blob_gas_price,
+        }
+    }
+}
+
+/// What a stored tx turns into when it's stored again: it's only overwritten when it moved to another block
+/// (i.e a reorg), storing it again in the same block is a no-op
+const TXS_CONFLICT: &str = " ON CONFLICT (hash) DO UPDATE SET block_hash = EXCLUDED.block_hash, pool_address = EXCLUDED.pool_address, price_pair = EXCLUDED.price_pair, fee_usdt = EXCLUDED.fee_usdt, effective_gas_price = EXCLUDED.effective_gas_price, gas_used = EXCLUDED.gas_used, fee_wei = EXCLUDED.fee_wei, base_fee_per_gas = EXCLUDED.base_fee_per_gas, priority_fee_per_gas = EXCLUDED.priority_fee_per_gas, burnt_fee_wei = EXCLUDED.burnt_fee_wei, priority_fee_wei = EXCLUDED.priority_fee_wei, blob_gas_used = EXCLUDED.blob_gas_used, blob_gas_price = EXCLUDED.blob_gas_price, blob_fee_wei = EXCLUDED.blob_fee_wei
+ WHERE txs.block_hash <> EXCLUDED.block_hash";
+
+/// Postgres' limit of bind parameters in a single statement
+const BIND_LIMIT: usize = 65_535;
+
+/// Stores the tx, see `TXS_CONFLICT` for a tx that's already stored
+pub async fn store_tx(
+    pool: &PgPool,
+    block_hash: &str,
+    liquidity_pool: &PoolConfig,
+    tx: &TxGas,
+    base_fee_per_gas: Option<u64>,
+    fee: &BigDecimal,
+) -> Result<()> {
+    let record = TxRecord {
+        pool: liquidity_pool.clone(),
+        tx: tx.clone(),
+        fee: fee.clone(),
+    };
+    let mut conn = pool.acquire().await?;
+    insert_txs(&mut conn, &[(block_hash, base_fee_per_gas, &record)]).await
+}
+
+/// Multi-row inserts the txs (block hash, block's base fee, tx), see `TXS_CONFLICT` for the ones already stored
+async fn insert_txs(conn: &mut PgConnection, txs: &[(&str, Option<u64>, &TxRecord)]) -> Result<()> {
+    for chunk in txs.chunks(BIND_LIMIT / 15) {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "INSERT INTO txs (hash, block_hash, pool_address, price_pair, fee_usdt, effective_gas_price, gas_used, fee_wei, base_fee_per_gas, priority_fee_per_gas, burnt_fee_wei, priority_fee_wei, blob_gas_used, blob_gas_price, blob_fee_wei) ",
+        );
+        query
+            .push_values(chunk, |mut row, (block_hash, base_fee_per_gas, record)| {
+                let (tx, base_fee_per_gas) = (&record.tx, *base_fee_per_gas);
+                row.push_bind(tx.hash.clone())
+                    .push_bind(block_hash.to_string())
+                    .push_bind(record.pool.address.to_string())
+                    .push_bind(record.pool.price_pair.clone())
+                    .push_bind(record.fee.clone())
+                    .push_bind(BigDecimal::from(tx.effective_gas_price))
+                    .push_bind(BigDecimal::from(tx.gas_used))
+                    .push_bind(tx.fee_wei())
+                    .push_bind(base_fee_per_gas.map(BigDecimal::from))
+                    .push_bind(
+                        tx.priority_fee_per_gas(base_fee_per_gas)
+                            .map(BigDecimal::from),
+                    )
+                    .push_bind(tx.burnt_fee_wei(base_fee_per_gas))
+                    .push_bind(tx.priority_fee_wei(base_fee_per_gas))
+                    .push_bind(tx.blob_gas_used.map(BigDecimal::from))
+                    .push_bind(tx.blob_gas_price.map(BigDecimal::from))
+                    .push_bind(tx.blob_fee_wei());
+            })
+            .push(TXS_CONFLICT);
+        query.build().execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// A pool tx along with its fee, priced with its pool's pair
+#[derive(Debug, Clone)]
+pub struct TxRecord {
+    pub pool: PoolConfig,
+    pub tx: TxGas,
+    /// in the quote currency of the pool's pair, stored in the `fee_usdt` column whatever the currency
+    pub fee: BigDecimal,
+}
+
+/// A block along with the prices of all its pairs and its pool txs
+#[derive(Debug, Clone)]
+pub struct BlockRecord {
+    pub header: Header,
+    pub prices: HashMap<String, Quote>,
+    pub txs: Vec<TxRecord>,
+}
+
+/// Stores the block along with the prices of all its pairs (in a single transaction), `eth_usdt` is the price of `price_pair`
+pub async fn store_block(
+    pool: &PgPool,
+    header: &Header,
+    price_pair: &str,
+    prices: &HashMap<String, Quote>,
+) -> Result<()> {
+    store_blocks(
+        pool,
+        price_pair,
+        &[BlockRecord {
+            header: header.clone(),
+            prices: prices.clone(),
+            txs: vec![],
+        }],
+    )
+    .await
+}
+
+/// Bulk stores the blocks along with their prices & txs, with multi-row inserts in a single transaction.
+///
+/// Storing is idempotent, so ranges that overlap the stored blocks (e.g a job over blocks the tracker stored)
+/// can be stored again: a stored block keeps its prices (& status), a stored tx is handled by `TXS_CONFLICT`.
+pub async fn store_blocks(pool: &PgPool, price_pair: &str, blocks: &[BlockRecord]) -> Result<()> {
+    let mut rows = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let hash = block.header.hash.to_string();
+        let eth_usdt = block
+            .prices
+            .get(price_pair)
+            .ok_or_else(|| eyre!("Missing {} price for block {}", price_pair, hash))?;
+        rows.push((block, hash, eth_usdt));
+    }
+    let prices = rows
+        .iter()
+        .flat_map(|(block, hash, _)| block.prices.iter().map(move |price| (*block, hash, price)))
+        .collect::<Vec<_>>();
+    let txs = rows
+        .iter()
+        .flat_map(|(block, hash, _)| {
+            block
+                .txs
+                .iter()
+                .map(move |tx| (hash.as_str(), block.header.base_fee_per_gas, tx))
+        })
+        .collect::<Vec<_>>();
+
+    let mut db_tx = pool.begin().await?;
+
+    for chunk in rows.chunks(BIND_LIMIT / 9) {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "INSERT INTO blocks (hash, number, eth_usdt, price_sources, price_timestamp, price_gap, base_fee_per_gas, blob_gas_used, excess_blob_gas) ",
+        );
+        query
+            .push_values(chunk, |mut row, (block, hash, eth_usdt)| {
+                let header = &block.header;
+                row.push_bind(hash.clone())
+                    .push_bind(header.number as i64)
+                    .push_bind(eth_usdt.price.clone())
+                    .push_bind(eth_usdt.sources.clone())
+                    .push_bind(eth_usdt.timestamp)
+                    .push_bind(price_gap(eth_usdt, header.timestamp))
+                    .push_bind(header.base_fee_per_gas.map(BigDecimal::from))
+                    .push_bind(header.blob_gas_used.map(BigDecimal::from))
+                    .push_bind(header.excess_blob_gas.map(BigDecimal::from));
+            })
+            .push(" ON CONFLICT (hash) DO NOTHING");
+        query.build().execute(&mut *db_tx).await?;
+    }
+
+    for chunk in prices.chunks(BIND_LIMIT / 6) {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "INSERT INTO block_prices (block_hash, pair, price, sources, price_timestamp, price_gap) ",
+        );
+        query
+            .push_values(chunk, |mut row, (block, hash, (pair, quote))| {
+                row.push_bind(hash.to_string())
+                    .push_bind(pair.to_string())
+                    .push_bind(quote.price.clone())
+                    .push_bind(quote.sources.clone())
+                    .push_bind(quote.timestamp)
+                    .push_bind(price_gap(quote, block.header.timestamp));
+            })
+            .push(" ON CONFLICT (block_hash, pair) DO NOTHING");
+        query.build().execute(&mut *db_tx).await?;
+    }
+
+    insert_txs(&mut db_tx, &txs).await?;
+
+    db_tx.commit().await?;
+    Ok(())
+}
+
+/// Seconds between the block and the price it got priced with, negative for a price observed before the block
+fn price_gap(quote: &Quote, block_timestamp: u64) -> Option<i64> {
+    quote
+        .timestamp
+        .map(|timestamp| timestamp - block_timestamp as i64)
+}
+
+/// The price `source` gave for `pair` at `timestamp` (along with when it was observed), if it's been fetched before
+pub async fn get_cached_price(
+    pool: &PgPool,
+    pair: &str,
+    source: &str,
+    staleness: &str,
+    timestamp: i64,
+) -> Result<Option<(BigDecimal, Option<i64>)>> {
+    let row = sqlx::query!(
+        "SELECT price, price_timestamp FROM prices WHERE pair = $1 AND source = $2 AND staleness = $3 AND timestamp = $4",
+        pair,
+        source,
+        staleness,
+        timestamp
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| (r.price, r.price_timestamp)))
+}
+
+pub async fn cache_price(
+    pool: &PgPool,
+    pair: &str,
+    source: &str,
+    staleness: &str,
+    timestamp: i64,
+    quote: &Quote,
+) -> Result<()> {
     sqlx::query!(
-        "INSERT INTO txs (hash, block_hash, fee_usdt) VALUES ($1, $2, $3)",
-        tx_hash,
-        block_hash,
-        fee_usdt
+        "INSERT INTO prices (pair, source, staleness, timestamp, price, price_timestamp) VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT DO NOTHING",
+        pair,
+        source,
+        staleness,
+        timestamp,
+        quote.price,
+        quote.timestamp
     )
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn store_block(
+/// Removes a block (and, through the cascade, all of its txs)
+pub async fn delete_block(pool: &PgPool, block_hash: &str) -> Result<u64> {
+    let result = sqlx::query!("DELETE FROM blocks WHERE hash = $1", block_hash)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Removes every block at `block_number` that isn't the canonical one, returns the removed blocks' hashes
+pub async fn delete_orphaned_blocks(
     pool: &PgPool,
     block_number: i64,
-    block_hash: &str,
-    eth_usdt: f64,
+    canonical_hash: &str,
+) -> Result<Vec<String>> {
+    let hashes = sqlx::query_scalar!(
+        "DELETE FROM blocks WHERE number = $1 AND hash <> $2 RETURNING hash",
+        block_number,
+        canonical_hash
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(hashes)
+}
+
+/// Returns the highest block the fee tracker went through, `None` if it never ran
+pub async fn tracked_block_number(pool: &PgPool) -> Result<Option<i64>> {
+    let last_block = sqlx::query_scalar!("SELECT last_block FROM fee_tracker_state")
+        .fetch_optional(pool)
+        .await?;
+    Ok(last_block)
+}
+
+/// Moves the fee tracker's high-water mark up to `block_number`, it never goes back down
+pub async fn store_tracked_block_number(pool: &PgPool, block_number: i64) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO fee_tracker_state (last_block) VALUES ($1)
+         ON CONFLICT (id) DO UPDATE SET last_block = GREATEST(fee_tracker_state.last_block, EXCLUDED.last_block), updated_at = NOW()",
+        block_number
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the (hash, number) of the highest stored block below `block_number`
+pub async fn latest_block_below(pool: &PgPool, block_number: i64) -> Result<Option<(String, i64)>> {
+    let row = sqlx::query!(
+        "SELECT hash, number FROM blocks WHERE number < $1 ORDER BY number DESC LIMIT 1",
+        block_number
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| (r.hash, r.number)))
+}
+
+/// Moves the stored blocks along `pending` -> `confirmed` -> `finalized`
+/// based on the chain's latest, `safe` and `finalized` heads.
+/// Only the blocks from `from` up are looked at, by default the ones above the last `finalized` head
+/// it went through (see `fee_tracker_state`), the stored blocks below it are all final already
+pub async fn refresh_block_statuses(
+    provider: &RootProvider<PubSubFrontend>,
+    pool: &PgPool,
+    confirmations: u64,
+    from: Option<u64>,
 ) -> Result<()> {
+    let head = provider.get_block_number().await?;
+    let safe = get_tagged_block_number(provider, BlockNumberOrTag::Safe).await?;
+    let finalized = get_tagged_block_number(provider, BlockNumberOrTag::Finalized).await?;
+
+    let lower = match from {
+        Some(from) => from as i64,
+        None => refreshed_block_number(pool)
+            .await?
+            .map_or(0, |number| number + 1),
+    };
+
     sqlx::query!(
-        "INSERT INTO blocks (hash, number, eth_usdt) VALUES ($1, $2, $3)",
-        block_hash,
-        block_number,
-        eth_usdt
+        "UPDATE blocks SET status = 'finalized' WHERE number >= $1 AND number <= $2 AND status <> 'finalized'",
+        lower,
+        finalized as i64
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE blocks SET status = 'confirmed' WHERE number >= $1 AND number <= $2 AND status = 'pending'",
+        lower,
+        confirmed_block_number(head, safe, confirmations) as i64
     )
     .execute(pool)
     .await?;
+
+    // the blocks below `from` might still be pending, only a full refresh moves the mark
+    if from.is_none() {
+        sqlx::query!(
+            "UPDATE fee_tracker_state SET finalized_block = GREATEST(finalized_block, $1), updated_at = NOW()",
+            finalized as i64
+        )
+        .execute(pool)
+        .await?;
+    }
     Ok(())
 }
 
-/// Provides the transaction fee in USDT for a given gas price, gas used and ETH/USDT price
-pub fn calculate_tx_fee_usdt(gas_price: u128, gas_used: u64, eth_usdt: f64) -> f64 {
-    // 1e-18 is the conversion factor from wei to ETH
-    (gas_price as f64 * gas_used as f64 * 1e-18) * eth_usdt
+/// Returns the highest `finalized` head the statuses got refreshed up to, `None` if they never were
+async fn refreshed_block_number(pool: &PgPool) -> Result<Option<i64>> {
+    let finalized_block = sqlx::query_scalar!("SELECT finalized_block FROM fee_tracker_state")
+        .fetch_optional(pool)
+        .await?;
+    Ok(finalized_block.flatten())
+}
+
+async fn get_tagged_block_number(
+    provider: &RootProvider<PubSubFrontend>,
+    tag: BlockNumberOrTag,
+) -> Result<u64> {
+    provider
+        .get_block_by_number(tag, BlockTransactionsKind::Hashes)
+        .await?
+        .map(|block| block.header.number)
+        .ok_or_else(|| eyre!("No block found for tag {}", tag))
+}
+
+/// The highest block that's considered confirmed - either buried under `confirmations` blocks
+/// or already deemed `safe` by the consensus layer
+fn confirmed_block_number(head: u64, safe: u64, confirmations: u64) -> u64 {
+    head.saturating_sub(confirmations).max(safe)
+}
+
+/// Provides the (exact) transaction fee for a given tx (blob fees included),
+/// in the quote currency of the given ETH price (e.g USDT for the ETH/USDT price)
+pub fn calculate_tx_fee(tx: &TxGas, eth_price: &BigDecimal) -> BigDecimal {
+    wei_to_eth(&tx.fee_wei()) * eth_price
+}
+
+/// Converts wei to ETH by shifting the decimal point, so no precision gets lost on the way
+pub fn wei_to_eth(wei: &BigDecimal) -> BigDecimal {
+    let (digits, scale) = wei.as_bigint_and_exponent();
+    BigDecimal::new(digits, scale + 18)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_tx_gas() {
+        let tx = TxGas {
+            hash: "0x0".to_string(),
+            effective_gas_price: 50_000_000_000, // 50 gwei
+            gas_used: 21000,
+            blob_gas_used: None,
+            blob_gas_price: None,
+        };
+
+        assert_eq!(tx.fee_wei(), BigDecimal::from(1_050_000_000_000_000_u64));
+        assert_eq!(
+            tx.priority_fee_per_gas(Some(48_000_000_000)),
+            Some(2_000_000_000)
+        );
+        assert_eq!(tx.priority_fee_per_gas(None), None); // pre-London
+
+        let burnt = tx.burnt_fee_wei(Some(48_000_000_000)).unwrap();
+        let priority = tx.priority_fee_wei(Some(48_000_000_000)).unwrap();
+        assert_eq!(burnt, BigDecimal::from(1_008_000_000_000_000_u64));
+        assert_eq!(priority, BigDecimal::from(42_000_000_000_000_u64));
+        assert_eq!(burnt + priority, tx.fee_wei());
+        assert_eq!(tx.burnt_fee_wei(None), None);
+        assert_eq!(tx.priority_fee_wei(None), None);
+        assert_eq!(tx.blob_fee_wei(), None);
+
+        let blob_tx = TxGas {
+            blob_gas_used: Some(131072),         // a single blob
+            blob_gas_price: Some(1_000_000_000), // 1 gwei
+            ..tx
+        };
+        assert_eq!(
+            blob_tx.blob_fee_wei(),
+            Some(BigDecimal::from(131_072_000_000_000_u64))
+        );
+        assert_eq!(
+            blob_tx.fee_wei(),
+            BigDecimal::from(1_050_000_000_000_000_u64 + 131_072_000_000_000_u64)
+        );
+    }
+
+    #[test]
+    fn test_confirmed_block_number() {
+        let test_cases = vec![
+            // (head, safe, confirmations, expected)
+            (21_000_100, 21_000_040, 12, 21_000_088), // depth is ahead of safe
+            (21_000_100, 21_000_095, 12, 21_000_095), // safe is ahead of depth
+            (21_000_100, 21_000_040, 0, 21_000_100),  // no depth required
+            (5, 0, 12, 0),                            // chain shorter than the depth
+        ];
+
+        for (head, safe, confirmations, expected) in test_cases {
+            assert_eq!(
+                confirmed_block_number(head, safe, confirmations),
+                expected,
+                "Failed for head={}, safe={}, confirmations={}",
+                head,
+                safe,
+                confirmations
+            );
+        }
+    }
 
     #[test]
-    fn test_calculate_tx_fee_usdt() {
+    fn test_calculate_tx_fee() {
         let test_cases = vec![
-            // (gas_price, gas_used, eth_usdt, expected_fee)
+            // (gas_price, gas_used, blob gas (used, price), eth_price, expected_fee)
             (
                 50_000_000_000, // 50 gwei
                 21000,          // standard transfer
-                2500.0,         // eth price
-                2.625,          // expected fee in USDT
+                None,           // no blobs
+                "2500.0",       // eth price
+                "2.625",        // expected fee in the quote currency
             ),
             (
                 100_000_000_000, // 100 gwei
                 100000,          //
-                2000.0,          //
-                20.0,            // expected fee
+                None,
+                "2000.0", //
+                "20.0",   // expected fee
             ),
             (
                 30_000_000_000, // 30 gwei
                 300000,         //
-                3000.0,         //
-                27.0,           // expected fee
+                None,
+                "3000.0", //
+                "27.0",   // expected fee
             ),
             (
                 200_000_000_000, // 200 gwei
                 21000,           //
-                1800.0,          //
-                7.56,            // expected fee
+                None,
+                "1800.0", //
+                "7.56",   // expected fee
             ),
             (
                 1_000_000_000, // 1 gwei
                 21000,         //
-                2000.0,        //
-                0.042,         // expected fee
+                None,
+                "2000.0", //
+                "0.042",  // expected fee
+            ),
+            (
+                123_456_789_012_345_678, // a fee that can't be represented by a f64
+                987_654_321,             //
+                None,
+                "3333.33",                           //
+                "406441697307.32468797947805208454", // expected fee
+            ),
+            (
+                10_000_000_000,                     // 10 gwei
+                100000,                             //
+                Some((131072, 1_000_000_000_u128)), // a single blob at 1 gwei
+                "2000.0",                           //
+                "2.262144",                         // expected fee, blob fee included
             ),
         ];
 
-        for (gas_price, gas_used, eth_usdt, expected) in test_cases {
-            let fee = calculate_tx_fee_usdt(gas_price, gas_used, eth_usdt);
-            assert!(
-                (fee - expected).abs() < 0.001,
-                "Failed for gas_price={}, gas_used={}, eth_price={}. Expected {}, got {}",
-                gas_price,
+        for (gas_price, gas_used, blob_gas, eth_price, expected) in test_cases {
+            let tx = TxGas {
+                hash: "0x0".to_string(),
+                effective_gas_price: gas_price,
                 gas_used,
-                eth_usdt,
-                expected,
-                fee
+                blob_gas_used: blob_gas.map(|(used, _)| used),
+                blob_gas_price: blob_gas.map(|(_, price)| price),
+            };
+            let eth_price = BigDecimal::from_str(eth_price).unwrap();
+            let expected = BigDecimal::from_str(expected).unwrap();
+
+            let fee = calculate_tx_fee(&tx, &eth_price);
+            assert_eq!(
+                fee, expected,
+                "Failed for gas_price={}, gas_used={}, eth_price={}",
+                gas_price, gas_used, eth_price
             );
         }
     }
+
+    #[test]
+    fn test_wei_to_eth() {
+        let cases = vec![
+            ("1", "0.000000000000000001"),
+            ("1000000000000000000", "1"),
+            ("1234567890123456789012", "1234.567890123456789012"),
+        ];
+
+        for (wei, expected) in cases {
+            let eth = wei_to_eth(&BigDecimal::from_str(wei).unwrap());
+            assert_eq!(eth, BigDecimal::from_str(expected).unwrap());
+        }
+    }
 }
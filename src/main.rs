@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use clap::Parser;
 use eyre::Result;
 use secrecy::ExposeSecret;
@@ -7,7 +9,11 @@ use tracing::info;
 use tx_fees::{
     args::{Args, Component},
     components::{api::ServerApp, fee_tracker::FeeTrackerApp, job_executor::JobExecutorApp},
-    configs::{FeeTrackerConfig, JobExecutorConfig, ServerConfig},
+    configs::{
+        validate_currencies, FeeTrackerConfig, FetchConfig, JobExecutorConfig, PriceSourceConfig,
+        RetryConfig, ServerConfig,
+    },
+    price_providers::{Staleness, VenueClients},
 };
 
 async fn run_migrations(db_pool: &PgPool) -> Result<()> {
@@ -41,14 +47,42 @@ async fn main() -> Result<()> {
      * 2. `JobExecutor` - responsible for executing the jobs that are scheduled by the user through the API
      * 3. `API` - responsible for exposing the API to the user
      */
+    let price_source = PriceSourceConfig {
+        sources: args.price_sources.clone(),
+        max_deviation: args.price_max_deviation.clone(),
+        fallbacks: args.price_fallback_sources.clone(),
+        timeout: Duration::from_secs(args.price_source_timeout),
+        binance_url: args.binance_url.clone(),
+        coinbase_url: args.coinbase_url.clone(),
+        kraken_url: args.kraken_url.clone(),
+        okx_url: args.okx_url.clone(),
+        clients: VenueClients::default(),
+        fixture: args.price_fixture.clone(),
+        staleness: Staleness {
+            strategy: args.price_strategy,
+            max_gap: args.price_max_gap,
+        },
+    };
+    let fetch = FetchConfig {
+        logs_block_range: args.logs_block_range,
+        concurrency: args.rpc_concurrency,
+    };
+
+    let pools = args.pools()?;
+    validate_currencies(&args.quote_currencies)?;
+
     let mut tasks = vec![];
     if args.components.contains(&Component::FeeTracker) {
         tasks.push(tokio::spawn(FeeTrackerApp::run(
             FeeTrackerConfig::new(
                 db_pool.clone(),
                 args.rpc_url.expose_secret().to_string().clone(),
-                args.liquidity_pool.clone(),
+                pools.clone(),
                 args.price_pair.clone(),
+                args.quote_currencies.clone(),
+                price_source.clone(),
+                args.confirmations,
+                fetch,
             )
             .await,
         )));
@@ -60,8 +94,17 @@ async fn main() -> Result<()> {
                 db_pool.clone(),
                 args.rpc_url.expose_secret().to_string().clone(),
                 args.redis_url.expose_secret().to_string().clone(),
-                args.liquidity_pool.clone(),
+                pools.clone(),
                 args.price_pair.clone(),
+                args.quote_currencies.clone(),
+                price_source.clone(),
+                args.confirmations,
+                fetch,
+                RetryConfig {
+                    max_retries: args.job_max_retries,
+                    backoff: Duration::from_secs(args.job_retry_backoff),
+                },
+                Duration::from_secs(args.job_heartbeat_ttl),
             )
             .await,
         )));
@@ -72,6 +115,7 @@ async fn main() -> Result<()> {
             ServerApp::build(ServerConfig::new(
                 db_pool.clone(),
                 args.redis_url.expose_secret().to_string().clone(),
+                price_source.supported_pairs(&pools, &args.price_pair),
                 args.api_host,
                 args.api_port,
             ))
@@ -1,19 +1,382 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use alloy::{providers::RootProvider, pubsub::PubSubFrontend};
+use async_trait::async_trait;
+use chrono::Utc;
+use clap::ValueEnum;
 use eyre::{eyre, Result};
-use reqwest::Client;
 use serde_json::Value;
+use sqlx::{types::BigDecimal, PgPool};
+use tracing::{info, warn};
+
+use crate::configs::{PoolConfig, PriceSourceConfig};
+
+mod cache;
+mod chainlink;
+mod client;
+mod coinbase;
+mod fallback;
+mod fixture;
+mod kraken;
+mod median;
+mod okx;
+mod uniswap;
+
+pub use cache::Cached;
+pub use chainlink::Chainlink;
+pub use client::{RateLimit, RateLimitedClient};
+pub use coinbase::{Coinbase, COINBASE_RATE_LIMIT, COINBASE_URL};
+pub use fallback::Fallback;
+pub use fixture::Fixture;
+pub use kraken::{Kraken, KRAKEN_RATE_LIMIT, KRAKEN_URL};
+pub use median::Median;
+pub use okx::{Okx, OKX_RATE_LIMIT, OKX_URL};
+pub use uniswap::UniswapV3;
+
+/// The block a price is requested for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PricedBlock {
+    pub number: u64,
+    /// `None` asks for the current (spot) price, i.e when tracking the chain head
+    pub timestamp: Option<i64>,
+}
+
+/// A price, along with where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub price: BigDecimal,
+    /// the sources that contributed to the price, more than one for an aggregated price
+    pub sources: Vec<String>,
+    /// when the price was observed, e.g the open time of the candle it's the close of.
+    /// `None` for a price read at the block itself (on-chain) or a spot price
+    pub timestamp: Option<i64>,
+}
+
+impl Quote {
+    pub fn new(price: BigDecimal, source: &str) -> Self {
+        Self {
+            price,
+            sources: vec![source.to_string()],
+            timestamp: None,
+        }
+    }
+
+    pub fn with_timestamp(self, timestamp: i64) -> Self {
+        Self {
+            timestamp: Some(timestamp),
+            ..self
+        }
+    }
+}
+
+/// Prices ETH in a given pair for a given block
+#[async_trait]
+pub trait PriceSource: Debug + Send + Sync {
+    fn name(&self) -> String;
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote>;
+
+    /// Bulk fetches the prices of `[start, end]` (timestamps) ahead of pricing the blocks in it one by one.
+    /// Best effort, whatever isn't prefetched gets fetched per block. A no-op for most sources
+    async fn prefetch(&self, _pair: &str, _start: i64, _end: i64) {}
+
+    /// Drops whatever got prefetched
+    fn clear_prefetched(&self) {}
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum PriceSourceKind {
+    /// Binance's 1s klines (spot price for the live blocks)
+    Binance,
+    /// Coinbase's 1m candles
+    Coinbase,
+    /// Kraken's 1m candles, Kraken only serves the last 720 of them (i.e ~12h back), older blocks can't be priced with it
+    Kraken,
+    /// OKX's 1m candles
+    Okx,
+    /// The tracked UniswapV3 pools' own `slot0` at the block
+    UniswapV3,
+    /// Chainlink's aggregators `latestRoundData` at the block
+    Chainlink,
+    /// A local CSV/JSON file, see [`Fixture`]
+    Fixture,
+}
+
+impl PriceSourceKind {
+    /// Whether the source can price `pair`, given the `pairs` it gets built with (see [`new_price_source`]).
+    /// The venues price any pair we can split into its currencies, the others only the ones they're built with
+    pub fn can_price(&self, pair: &str, pairs: &[String]) -> bool {
+        match self {
+            PriceSourceKind::Binance
+            | PriceSourceKind::Coinbase
+            | PriceSourceKind::Kraken
+            | PriceSourceKind::Okx => split_pair(pair).is_some(),
+            PriceSourceKind::UniswapV3 | PriceSourceKind::Chainlink | PriceSourceKind::Fixture => {
+                pairs.iter().any(|configured| configured == pair)
+            }
+        }
+    }
+}
+
+/// What a block with no candle at its timestamp gets priced with
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum PriceStrategy {
+    /// The closest candle, before or after the block, at most `max_gap` seconds away
+    Nearest,
+    /// Linearly interpolated between the candles surrounding the block, each at most `max_gap` seconds away
+    Interpolate,
+    /// Only ever the candle at the block's timestamp
+    Fail,
+}
+
+/// How stale a block's (historical) price is allowed to be
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Staleness {
+    pub strategy: PriceStrategy,
+    /// seconds between the block and the candle(s) it gets priced with
+    pub max_gap: i64,
+}
+
+impl Staleness {
+    /// Identifies the settings in the prices cache, e.g `nearest:60`
+    pub fn key(&self) -> String {
+        format!("{:?}:{}", self.strategy, self.max_gap).to_lowercase()
+    }
+
+    /// Prices `timestamp` from the candles (open time -> close), along with the open time of the candle
+    /// it got priced with (for an interpolated price, the farthest of the two)
+    fn resolve(
+        &self,
+        closes: &BTreeMap<i64, BigDecimal>,
+        timestamp: i64,
+    ) -> Option<(BigDecimal, i64)> {
+        if let Some(close) = closes.get(&timestamp) {
+            return Some((close.clone(), timestamp));
+        }
+
+        let within_gap = |(open_time, close): (&i64, &BigDecimal)| {
+            ((open_time - timestamp).abs() <= self.max_gap).then(|| (*open_time, close.clone()))
+        };
+        let before = closes.range(..timestamp).next_back().and_then(within_gap);
+        let after = closes.range(timestamp + 1..).next().and_then(within_gap);
+
+        match self.strategy {
+            PriceStrategy::Fail => None,
+            // ties go to the earlier candle, i.e the price the block could have actually seen
+            PriceStrategy::Nearest => match (before, after) {
+                (Some(before), Some(after)) if after.0 - timestamp < timestamp - before.0 => {
+                    Some(after)
+                }
+                (Some(before), _) => Some(before),
+                (None, after) => after,
+            }
+            .map(|(open_time, close)| (close, open_time)),
+            PriceStrategy::Interpolate => {
+                let ((before_ts, before), (after_ts, after)) = (before?, after?);
+                // rounded to the candles' own precision, the division could otherwise be never ending
+                let scale = before
+                    .fractional_digit_count()
+                    .max(after.fractional_digit_count());
+                let price = &before
+                    + (&after - &before) * BigDecimal::from(timestamp - before_ts)
+                        / BigDecimal::from(after_ts - before_ts);
+                let farthest = if after_ts - timestamp > timestamp - before_ts {
+                    after_ts
+                } else {
+                    before_ts
+                };
+                Some((price.round(scale), farthest))
+            }
+        }
+    }
+}
+
+/// Builds the configured price source, able to price every one of the `pairs`.
+/// Several sources get aggregated into their median (see [`Median`]),
+/// the fallback sources are tried in order whenever that fails (see [`Fallback`]).
+/// The historical prices of every source are cached in the DB (see [`Cached`])
+pub async fn new_price_source(
+    config: &PriceSourceConfig,
+    db_pool: &PgPool,
+    provider: &RootProvider<PubSubFrontend>,
+    pairs: &[String],
+    pools: &[PoolConfig],
+) -> Result<Arc<dyn PriceSource>> {
+    let mut sources = Vec::new();
+    for kind in &config.sources {
+        sources
+            .push(new_single_price_source(*kind, config, db_pool, provider, pairs, pools).await?);
+    }
+
+    let source: Arc<dyn PriceSource> = match sources.len() {
+        0 => return Err(eyre!("No price source configured")),
+        1 => sources.remove(0),
+        _ => Arc::new(Median::new(sources, config.max_deviation.clone())),
+    };
+    if config.fallbacks.is_empty() {
+        return Ok(source);
+    }
+
+    let mut chain = vec![source];
+    for kind in &config.fallbacks {
+        chain.push(new_single_price_source(*kind, config, db_pool, provider, pairs, pools).await?);
+    }
+    Ok(Arc::new(Fallback::new(chain, config.timeout)))
+}
+
+async fn new_single_price_source(
+    kind: PriceSourceKind,
+    config: &PriceSourceConfig,
+    db_pool: &PgPool,
+    provider: &RootProvider<PubSubFrontend>,
+    pairs: &[String],
+    pools: &[PoolConfig],
+) -> Result<Arc<dyn PriceSource>> {
+    let source: Arc<dyn PriceSource> = match kind {
+        PriceSourceKind::Binance => Arc::new(HttpSource::new(
+            "binance",
+            &config.binance_url,
+            Binance::with_base_url,
+            config.clients.binance.clone(),
+            config.staleness,
+        )),
+        PriceSourceKind::Coinbase => Arc::new(HttpSource::new(
+            "coinbase",
+            &config.coinbase_url,
+            Coinbase::with_base_url,
+            config.clients.coinbase.clone(),
+            config.staleness,
+        )),
+        PriceSourceKind::Kraken => Arc::new(HttpSource::new(
+            "kraken",
+            &config.kraken_url,
+            Kraken::with_base_url,
+            config.clients.kraken.clone(),
+            config.staleness,
+        )),
+        PriceSourceKind::Okx => Arc::new(HttpSource::new(
+            "okx",
+            &config.okx_url,
+            Okx::with_base_url,
+            config.clients.okx.clone(),
+            config.staleness,
+        )),
+        PriceSourceKind::UniswapV3 => {
+            Arc::new(UniswapV3::new(provider.clone(), pools, pairs).await?)
+        }
+        PriceSourceKind::Chainlink => Arc::new(Chainlink::new(provider.clone(), pairs).await?),
+        PriceSourceKind::Fixture => {
+            Arc::new(Fixture::load(config.fixture.as_deref().ok_or_else(
+                || eyre!("The fixture price source requires a fixture file"),
+            )?)?)
+        }
+    };
+    Ok(Arc::new(Cached::new(
+        source,
+        db_pool.clone(),
+        config.staleness,
+    )))
+}
+
+/// The venues' clients, built once per process and shared by all of its price sources (the fee tracker's,
+/// the job executor's, a venue that's both aggregated & a fallback), so they're all within the venue's rate limit together
+#[derive(Debug, Clone)]
+pub struct VenueClients {
+    pub binance: Arc<RateLimitedClient>,
+    pub coinbase: Arc<RateLimitedClient>,
+    pub kraken: Arc<RateLimitedClient>,
+    pub okx: Arc<RateLimitedClient>,
+}
+
+impl Default for VenueClients {
+    fn default() -> Self {
+        Self {
+            binance: Arc::new(RateLimitedClient::new(BINANCE_RATE_LIMIT)),
+            coinbase: Arc::new(RateLimitedClient::new(COINBASE_RATE_LIMIT)),
+            kraken: Arc::new(RateLimitedClient::new(KRAKEN_RATE_LIMIT)),
+            okx: Arc::new(RateLimitedClient::new(OKX_RATE_LIMIT)),
+        }
+    }
+}
+
+/// Quote currencies we know how to split a pair by, longest first so `USDT` isn't taken for `USD`
+pub const QUOTES: &[&str] = &["USDT", "USDC", "EUR", "GBP", "USD", "BTC"];
+
+/// Whether `currency` is one we know how to price ETH in, i.e one of `QUOTES`
+pub fn is_quote_currency(currency: &str) -> bool {
+    QUOTES.contains(&currency.trim().to_uppercase().as_str())
+}
+
+/// Splits our `<base><quote>` pair (e.g `ETHUSDT`) into its base & quote currencies
+fn split_pair(pair: &str) -> Option<(&str, &str)> {
+    QUOTES.iter().find_map(|quote| {
+        pair.strip_suffix(quote)
+            .filter(|base| !base.is_empty())
+            .map(|base| (base, *quote))
+    })
+}
+
+/// The venue's `<base><separator><quote>` symbol of the pair, unknown pairs are passed through as is
+fn venue_symbol(pair: &str, separator: &str) -> String {
+    match split_pair(pair) {
+        Some((base, quote)) => format!("{}{}{}", base, separator, quote),
+        None => pair.to_string(),
+    }
+}
+
+/// Start of the 1m candle `timestamp` falls in
+fn minute_start(timestamp: i64) -> i64 {
+    timestamp - timestamp.rem_euclid(60)
+}
 
 pub trait PriceProvider {
     fn url(&self, timestamp: Option<i64>) -> String;
-    fn extract_price(&self, data: &Value) -> Option<f64>;
+    /// The price in the venue's response. For a historical one, only the price of the candle `timestamp` falls in,
+    /// venues answer with the candles they have (e.g the latest ones) rather than none
+    fn extract_price(&self, data: &Value, timestamp: Option<i64>) -> Option<BigDecimal>;
+
+    /// How many seconds back the venue serves candles, `None` if it serves all of them
+    fn history(&self) -> Option<i64> {
+        None
+    }
+
+    /// The URL of a page of the candles opened in `[start, end]`, `None` if the venue can't be bulk fetched
+    fn series_url(&self, _start: i64, _end: i64) -> Option<String> {
+        None
+    }
+
+    /// (open time, close price) of each candle of the page, oldest first
+    fn extract_series(&self, _data: &Value) -> Option<Vec<(i64, BigDecimal)>> {
+        None
+    }
 }
 
+pub const BINANCE_URL: &str = "https://api.binance.com";
+/// 6000 request weight per minute, a klines call weighing 2
+pub const BINANCE_RATE_LIMIT: RateLimit = RateLimit {
+    requests_per_second: 20.0,
+    burst: 20.0,
+    weight_header: Some(("x-mbx-used-weight-1m", 6000)),
+};
+
+#[derive(Debug)]
 pub struct Binance {
+    base_url: String,
     pair: String,
 }
 
 impl Binance {
     pub fn new(pair: &str) -> Self {
+        Self::with_base_url(pair, BINANCE_URL)
+    }
+
+    pub fn with_base_url(pair: &str, base_url: &str) -> Self {
         Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
             pair: pair.to_string(),
         }
     }
@@ -22,8 +385,8 @@ impl Binance {
 impl PriceProvider for Binance {
     fn url(&self, timestamp: Option<i64>) -> String {
         let base = format!(
-            "https://api.binance.com/api/v3/klines?symbol={}&interval=1s&limit=1",
-            self.pair
+            "{}/api/v3/klines?symbol={}&interval=1s&limit=1",
+            self.base_url, self.pair
         );
         match timestamp {
             Some(ts) => format!("{}&startTime={}", base, ts * 1000),
@@ -31,44 +394,849 @@ impl PriceProvider for Binance {
         }
     }
 
-    fn extract_price(&self, data: &Value) -> Option<f64> {
+    fn extract_price(&self, data: &Value, timestamp: Option<i64>) -> Option<BigDecimal> {
+        let kline = data.as_array()?.first()?.as_array()?;
+        // `startTime` gets the first kline opened at or after it, i.e a later one when there's none at `timestamp`
+        if timestamp.is_some_and(|ts| kline.first().and_then(Value::as_i64) != Some(ts * 1000)) {
+            return None;
+        }
+        // prices are string encoded, so they can be parsed without going through a float
+        BigDecimal::from_str(kline.get(4)?.as_str()?).ok()
+    }
+
+    fn series_url(&self, start: i64, end: i64) -> Option<String> {
+        Some(format!(
+            "{}/api/v3/klines?symbol={}&interval=1s&limit={}&startTime={}&endTime={}",
+            self.base_url,
+            self.pair,
+            BINANCE_KLINES_PAGE,
+            start * 1000,
+            end * 1000
+        ))
+    }
+
+    fn extract_series(&self, data: &Value) -> Option<Vec<(i64, BigDecimal)>> {
         data.as_array()?
-            .first()?
-            .as_array()?
-            .get(4)?
-            .as_str()?
-            .parse()
-            .ok()
+            .iter()
+            .map(|kline| {
+                let kline = kline.as_array()?;
+                let open_time = kline.first()?.as_i64()? / 1000;
+                Some((
+                    open_time,
+                    BigDecimal::from_str(kline.get(4)?.as_str()?).ok()?,
+                ))
+            })
+            .collect()
     }
 }
 
-pub async fn get_pair_price(provider: &impl PriceProvider, timestamp: Option<i64>) -> Result<f64> {
-    let response = Client::new()
-        .get(provider.url(timestamp))
-        .send()
-        .await?
-        .json::<Value>()
-        .await?;
+/// The most klines Binance returns per call
+const BINANCE_KLINES_PAGE: usize = 1000;
+
+pub async fn get_pair_price(
+    client: &RateLimitedClient,
+    provider: &impl PriceProvider,
+    timestamp: Option<i64>,
+) -> Result<BigDecimal> {
+    if let (Some(ts), Some(history)) = (timestamp, provider.history()) {
+        if ts < Utc::now().timestamp() - history {
+            return Err(eyre!(
+                "No price at {}, the venue only serves the last {}s of candles",
+                ts,
+                history
+            ));
+        }
+    }
+    let response = client.get_json(&provider.url(timestamp)).await?;
 
     provider
-        .extract_price(&response)
-        .ok_or_else(|| eyre!("Failed to parse price from provider response"))
+        .extract_price(&response, timestamp)
+        .ok_or_else(|| match timestamp {
+            Some(ts) => eyre!("No candle at {} in the provider response", ts),
+            None => eyre!("Failed to parse price from provider response"),
+        })
+}
+
+/// The fetched candles of a pair, open time -> close price
+#[derive(Debug, Default)]
+struct PriceSeries {
+    start: i64,
+    end: i64,
+    closes: BTreeMap<i64, BigDecimal>,
+}
+
+/// Seconds of candles held at once per pair, a prefetched range is paged through a window at a time
+const PREFETCH_WINDOW: i64 = 3600;
+
+/// The range a pair got prefetched for, along with the window of its candles fetched so far
+#[derive(Debug)]
+struct Prefetched {
+    start: i64,
+    end: i64,
+    window: PriceSeries,
+}
+
+impl PriceSeries {
+    /// Whether the series holds all the candles `timestamp` could be priced with
+    fn covers(&self, timestamp: i64, staleness: &Staleness) -> bool {
+        timestamp - staleness.max_gap >= self.start && timestamp + staleness.max_gap <= self.end
+    }
+}
+
+/// Prices through an HTTP venue, with a [`PriceProvider`] per pair.
+/// All the pairs' requests share the venue's client, i.e its rate limit (see [`VenueClients`])
+///
+/// The historical prices of the venues serving candle series are bound by `staleness`,
+/// the other venues are priced with the 1m candle the block falls in, timestamped with its open time
+#[derive(Debug)]
+pub struct HttpSource<P> {
+    name: &'static str,
+    base_url: String,
+    provider: fn(&str, &str) -> P, // (pair, base url) -> provider
+    client: Arc<RateLimitedClient>,
+    staleness: Staleness,
+    prefetched: RwLock<HashMap<String, Prefetched>>, // pair -> prefetched range
+}
+
+impl<P> HttpSource<P> {
+    pub fn new(
+        name: &'static str,
+        base_url: &str,
+        provider: fn(&str, &str) -> P,
+        client: Arc<RateLimitedClient>,
+        staleness: Staleness,
+    ) -> Self {
+        Self {
+            name,
+            base_url: base_url.to_string(),
+            provider,
+            client,
+            staleness,
+            prefetched: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl<P: PriceProvider> HttpSource<P> {
+    /// Prices `ts` from the fetched candles, see [`Staleness::resolve`]
+    fn quote_at(&self, closes: &BTreeMap<i64, BigDecimal>, pair: &str, ts: i64) -> Result<Quote> {
+        let (price, price_ts) = self.staleness.resolve(closes, ts).ok_or_else(|| {
+            eyre!(
+                "No {} price within {}s of {} ({:?})",
+                pair,
+                self.staleness.max_gap,
+                ts,
+                self.staleness.strategy
+            )
+        })?;
+        Ok(Quote::new(price, self.name).with_timestamp(price_ts))
+    }
+
+    /// Pages through the venue's candles opened in `[start, end]`, a failing page leaves the series cut short.
+    /// `None` if the venue can't be bulk fetched
+    async fn fetch_series(
+        &self,
+        provider: &P,
+        pair: &str,
+        start: i64,
+        end: i64,
+    ) -> Option<PriceSeries> {
+        let mut series = PriceSeries {
+            start,
+            end,
+            closes: BTreeMap::new(),
+        };
+
+        let mut page_start = start;
+        while page_start <= end {
+            let url = provider.series_url(page_start, end)?;
+            let page = match self.client.get_json(&url).await {
+                Ok(data) => provider.extract_series(&data),
+                Err(err) => {
+                    warn!(source = %self.name, pair = %pair, error = %err, "failed to fetch prices |");
+                    None
+                }
+            };
+            let Some(last) = page
+                .as_ref()
+                .and_then(|page| page.last())
+                .map(|(ts, _)| *ts)
+            else {
+                series.end = page_start - 1;
+                break;
+            };
+
+            series.closes.extend(page.into_iter().flatten());
+            page_start = last + 1;
+        }
+        Some(series)
+    }
+
+    /// The candles to price the blocks of `[start, end]` with, at most `PREFETCH_WINDOW` seconds of them.
+    /// Widened by the max gap so the edge blocks can be priced too
+    async fn fetch_window(
+        &self,
+        provider: &P,
+        pair: &str,
+        start: i64,
+        end: i64,
+    ) -> Option<PriceSeries> {
+        let max_gap = self.staleness.max_gap;
+        let end = end.min(start.saturating_add(PREFETCH_WINDOW));
+        let window = self
+            .fetch_series(provider, pair, start - max_gap, end + max_gap)
+            .await?;
+
+        info!(
+            source = %self.name,
+            pair = %pair,
+            start = window.start,
+            end = window.end,
+            candles = window.closes.len(),
+            "prefetched prices |"
+        );
+        Some(window)
+    }
+}
+
+#[async_trait]
+impl<P: PriceProvider + Debug + Send + Sync> PriceSource for HttpSource<P> {
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote> {
+        let provider = (self.provider)(pair, &self.base_url);
+        let Some(ts) = block.timestamp else {
+            let price = get_pair_price(&self.client, &provider, None).await?;
+            return Ok(Quote::new(price, self.name));
+        };
+
+        let prefetched_end = {
+            let prefetched = self.prefetched.read().expect("Poisoned prefetched prices");
+            match prefetched.get(pair) {
+                // the window has all the candles around the block, a gap in them isn't fetched again
+                Some(range) if range.window.covers(ts, &self.staleness) => {
+                    return self.quote_at(&range.window.closes, pair, ts);
+                }
+                Some(range) => (range.start..=range.end).contains(&ts).then_some(range.end),
+                None => None,
+            }
+        };
+
+        // the block is past the prefetched window, the next one starts at it
+        if let Some(end) = prefetched_end {
+            if let Some(window) = self.fetch_window(&provider, pair, ts, end).await {
+                let covered = window.covers(ts, &self.staleness);
+                let quote = self.quote_at(&window.closes, pair, ts);
+                if let Some(range) = self
+                    .prefetched
+                    .write()
+                    .expect("Poisoned prefetched prices")
+                    .get_mut(pair)
+                {
+                    range.window = window;
+                }
+                if covered {
+                    return quote;
+                }
+            }
+        }
+
+        let max_gap = self.staleness.max_gap;
+        match self
+            .fetch_series(&provider, pair, ts - max_gap, ts + max_gap)
+            .await
+        {
+            Some(series) => self.quote_at(&series.closes, pair, ts),
+            // priced with the 1m candle the block falls in, its open time is the price's timestamp
+            None => {
+                let price = get_pair_price(&self.client, &provider, Some(ts)).await?;
+                Ok(Quote::new(price, self.name).with_timestamp(minute_start(ts)))
+            }
+        }
+    }
+
+    /// Fetches the candles of `[start, end]` a window at a time (see `PREFETCH_WINDOW`),
+    /// the first one right away, the next ones once the blocks get priced past the current one
+    async fn prefetch(&self, pair: &str, start: i64, end: i64) {
+        let provider = (self.provider)(pair, &self.base_url);
+        let Some(window) = self.fetch_window(&provider, pair, start, end).await else {
+            return;
+        };
+
+        self.prefetched
+            .write()
+            .expect("Poisoned prefetched prices")
+            .insert(pair.to_string(), Prefetched { start, end, window });
+    }
+
+    fn clear_prefetched(&self) {
+        self.prefetched
+            .write()
+            .expect("Poisoned prefetched prices")
+            .clear();
+    }
+}
+
+/// Prices every one of the `pairs` at the given block. Only the `required` ones fail the block,
+/// the others (the quote currencies' pairs) are best effort, one the source can't price is left out
+pub async fn get_pairs_prices(
+    source: &dyn PriceSource,
+    pairs: &[String],
+    required: &[String],
+    block: PricedBlock,
+) -> Result<HashMap<String, Quote>> {
+    let mut prices = HashMap::new();
+    for pair in pairs {
+        match source.get_price(pair, block).await {
+            Ok(quote) => {
+                prices.insert(pair.clone(), quote);
+            }
+            Err(err) if !required.contains(pair) => warn!(
+                source = %source.name(),
+                pair = %pair,
+                block_number = block.number,
+                error = %err,
+                "quote currency not priced, skipped |"
+            ),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(prices)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use tokio::test;
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[test]
+    async fn test_binance_extract_price() {
+        let provider = Binance::new("ETHUSDT");
+        let data = serde_json::json!([[
+            1706826922000_i64,
+            "2297.11000000",
+            "2297.12000000",
+            "2297.11000000",
+            "2297.12000000",
+            "1.52910000",
+            1706826922999_i64,
+            "3512.49361700",
+            8,
+            "0.92310000",
+            "2120.42100390",
+            "0"
+        ]]);
+
+        let cases = vec![
+            (None, Some("2297.12")),
+            (Some(1706826922), Some("2297.12")),
+            (Some(1706826921), None), // a kline opened after the requested one
+        ];
+
+        for (timestamp, expected) in cases {
+            assert_eq!(
+                provider.extract_price(&data, timestamp),
+                expected.map(|price| BigDecimal::from_str(price).unwrap()),
+                "Failed for {:?}",
+                timestamp
+            );
+        }
+        assert_eq!(provider.extract_price(&serde_json::json!([]), None), None);
+    }
+
+    #[test]
+    async fn test_binance_series() {
+        let provider = Binance::new("ETHUSDT");
+        let kline = |open_time: i64, close: &str| {
+            serde_json::json!([
+                open_time,
+                "2297.11",
+                "2297.12",
+                "2297.11",
+                close,
+                "1.52",
+                open_time + 999,
+                "3512.49",
+                8,
+                "0.92",
+                "2120.42",
+                "0"
+            ])
+        };
+
+        assert_eq!(
+            provider.series_url(1706826922, 1706827921),
+            Some("https://api.binance.com/api/v3/klines?symbol=ETHUSDT&interval=1s&limit=1000&startTime=1706826922000&endTime=1706827921000".to_string())
+        );
+        assert_eq!(
+            provider.extract_series(&serde_json::json!([
+                kline(1706826922000, "2297.12"),
+                kline(1706826924000, "2297.50")
+            ])),
+            Some(vec![
+                (1706826922, BigDecimal::from_str("2297.12").unwrap()),
+                (1706826924, BigDecimal::from_str("2297.50").unwrap())
+            ])
+        );
+        assert_eq!(
+            provider.extract_series(&serde_json::json!([])),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    async fn test_staleness_resolve() {
+        let closes = BTreeMap::from([
+            (100, BigDecimal::from_str("10").unwrap()),
+            (101, BigDecimal::from_str("20").unwrap()),
+            (105, BigDecimal::from_str("30.00").unwrap()), // no candles in between
+            (200, BigDecimal::from_str("40").unwrap()),
+        ]);
+        let staleness = |strategy| Staleness {
+            strategy,
+            max_gap: 10,
+        };
+
+        let cases = vec![
+            // (strategy, timestamp, expected (price, price timestamp))
+            (PriceStrategy::Nearest, 101, Some(("20", 101))),
+            (PriceStrategy::Nearest, 102, Some(("20", 101))),
+            (PriceStrategy::Nearest, 103, Some(("20", 101))), // tie, the earlier candle
+            (PriceStrategy::Nearest, 104, Some(("30.00", 105))),
+            (PriceStrategy::Nearest, 115, Some(("30.00", 105))),
+            (PriceStrategy::Nearest, 116, None), // both past the max gap
+            (PriceStrategy::Nearest, 190, Some(("40", 200))),
+            (PriceStrategy::Interpolate, 101, Some(("20", 101))),
+            (PriceStrategy::Interpolate, 102, Some(("22.50", 105))),
+            (PriceStrategy::Interpolate, 104, Some(("27.50", 101))),
+            (PriceStrategy::Interpolate, 106, None), // nothing after within the max gap
+            (PriceStrategy::Interpolate, 99, None),  // nothing before
+            (PriceStrategy::Fail, 101, Some(("20", 101))),
+            (PriceStrategy::Fail, 102, None),
+        ];
+
+        for (strategy, timestamp, expected) in cases {
+            assert_eq!(
+                staleness(strategy).resolve(&closes, timestamp),
+                expected.map(|(price, ts)| (BigDecimal::from_str(price).unwrap(), ts)),
+                "Failed for {:?} at {}",
+                strategy,
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    async fn test_price_series_covers() {
+        let series = PriceSeries {
+            start: 100,
+            end: 120,
+            closes: BTreeMap::from([
+                (100, BigDecimal::from(1)),
+                (101, BigDecimal::from(2)),
+                (105, BigDecimal::from(3)), // no candles in between
+            ]),
+        };
+        let staleness = Staleness {
+            strategy: PriceStrategy::Nearest,
+            max_gap: 5,
+        };
+
+        let cases = vec![
+            (105, true),
+            (111, true),  // no candle within the max gap, but none to fetch either
+            (104, false), // the candles before it aren't covered by the series
+            (116, false), // neither are the ones after it
+        ];
+
+        for (timestamp, expected) in cases {
+            assert_eq!(
+                series.covers(timestamp, &staleness),
+                expected,
+                "Failed for {}",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    async fn test_http_source_base_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/klines"))
+            .and(query_param("symbol", "ETHUSDT"))
+            .and(query_param("startTime", "1706826862000")) // max gap before the block
+            .and(query_param("endTime", "1706826982000"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!([[
+                    1706826922000_i64,
+                    "2297.11000000",
+                    "2297.12000000",
+                    "2297.11000000",
+                    "2297.12000000",
+                    "1.52910000",
+                    1706826922999_i64,
+                    "3512.49361700",
+                    8,
+                    "0.92310000",
+                    "2120.42100390",
+                    "0"
+                ]])),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let source = HttpSource::new(
+            "binance",
+            &server.uri(),
+            Binance::with_base_url,
+            Arc::new(RateLimitedClient::new(BINANCE_RATE_LIMIT)),
+            Staleness {
+                strategy: PriceStrategy::Nearest,
+                max_gap: 60,
+            },
+        );
+        let quote = source
+            .get_price(
+                "ETHUSDT",
+                PricedBlock {
+                    number: 19134000,
+                    timestamp: Some(1706826922),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            quote,
+            Quote::new(BigDecimal::from_str("2297.12").unwrap(), "binance")
+                .with_timestamp(1706826922)
+        );
+    }
+
+    /// Binance's 1s klines of the requested page, each one closing at its open time (in seconds),
+    /// but for the ones opened in `gap`
+    struct Klines {
+        gap: std::ops::Range<i64>,
+    }
+
+    impl wiremock::Respond for Klines {
+        fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+            let param = |name: &str| {
+                request
+                    .url
+                    .query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.parse::<i64>().unwrap() / 1000)
+                    .unwrap()
+            };
+            let (start, end) = (param("startTime"), param("endTime"));
+            let klines = (start..=end.min(start + BINANCE_KLINES_PAGE as i64 - 1))
+                .filter(|ts| !self.gap.contains(ts))
+                .map(|ts| {
+                    serde_json::json!([
+                        ts * 1000,
+                        "1",
+                        "1",
+                        "1",
+                        ts.to_string(),
+                        "1",
+                        ts * 1000 + 999,
+                        "1",
+                        1,
+                        "1",
+                        "1",
+                        "0"
+                    ])
+                })
+                .collect::<Vec<_>>();
+            ResponseTemplate::new(200).set_body_json(klines)
+        }
+    }
+
+    #[test]
+    async fn test_http_source_prefetch_window() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/klines"))
+            .respond_with(Klines { gap: 0..0 })
+            .mount(&server)
+            .await;
+
+        let source = HttpSource::new(
+            "binance",
+            &server.uri(),
+            Binance::with_base_url,
+            Arc::new(RateLimitedClient::new(BINANCE_RATE_LIMIT)),
+            Staleness {
+                strategy: PriceStrategy::Nearest,
+                max_gap: 60,
+            },
+        );
+        let block = |timestamp| PricedBlock {
+            number: 19134000,
+            timestamp: Some(timestamp),
+        };
+        let requests = || async { server.received_requests().await.unwrap().len() };
+
+        // a day of blocks, only the first window is fetched upfront
+        let start = 1706826922;
+        source.prefetch("ETHUSDT", start, start + 86_400).await;
+        assert_eq!(requests().await, 4);
+
+        let cases = vec![
+            // (timestamp, expected requests)
+            (start, 4),
+            (start + PREFETCH_WINDOW, 4), // the end of the first window
+            (start + PREFETCH_WINDOW + 60, 8), // the next window
+            (start + PREFETCH_WINDOW + 120, 8),
+        ];
+
+        for (timestamp, expected_requests) in cases {
+            let quote = source.get_price("ETHUSDT", block(timestamp)).await.unwrap();
+            assert_eq!(
+                quote,
+                Quote::new(BigDecimal::from(timestamp), "binance").with_timestamp(timestamp),
+                "Failed for {}",
+                timestamp
+            );
+            assert_eq!(
+                requests().await,
+                expected_requests,
+                "Failed for {}",
+                timestamp
+            );
+        }
+
+        // only the current window is held in memory
+        let prefetched = source.prefetched.read().unwrap();
+        assert!(prefetched["ETHUSDT"].window.closes.len() as i64 <= PREFETCH_WINDOW + 121);
+    }
+
+    #[test]
+    async fn test_http_source_prefetch_gap() {
+        let start = 1706826922;
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/klines"))
+            .respond_with(Klines {
+                gap: start + 600..start + 1200,
+            })
+            .mount(&server)
+            .await;
+
+        let source = HttpSource::new(
+            "binance",
+            &server.uri(),
+            Binance::with_base_url,
+            Arc::new(RateLimitedClient::new(BINANCE_RATE_LIMIT)),
+            Staleness {
+                strategy: PriceStrategy::Nearest,
+                max_gap: 60,
+            },
+        );
+        let block = |timestamp| PricedBlock {
+            number: 19134000,
+            timestamp: Some(timestamp),
+        };
+        let requests = || async { server.received_requests().await.unwrap().len() };
+
+        source.prefetch("ETHUSDT", start, start + 86_400).await;
+        let prefetched = requests().await;
+
+        let cases = vec![
+            // (timestamp, priced)
+            (start + 630, true), // the candle right before the gap
+            (start + 700, false),
+            (start + 800, false),
+            (start + 1150, true), // the candle right after the gap
+        ];
+
+        for (timestamp, priced) in cases {
+            let quote = source.get_price("ETHUSDT", block(timestamp)).await;
+            assert_eq!(quote.is_ok(), priced, "Failed for {}", timestamp);
+            // the gap is in the window already fetched, it isn't fetched again
+            assert_eq!(requests().await, prefetched, "Failed for {}", timestamp);
+        }
+    }
+
+    #[test]
+    async fn test_http_source_candle_time() {
+        let server = MockServer::start().await;
+        let now = minute_start(Utc::now().timestamp());
+        // Kraken answers with its latest candles, whatever the `since`
+        Mock::given(method("GET"))
+            .and(path("/0/public/OHLC"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"error": [], "result": {
+                    "XETHZUSD": [[now - 60, "2296.61", "2297.90", "2296.50", "2297.12", "2297.01", "104.6", 85]],
+                    "last": now - 60
+                }})),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let source = HttpSource::new(
+            "kraken",
+            &server.uri(),
+            Kraken::with_base_url,
+            Arc::new(RateLimitedClient::new(KRAKEN_RATE_LIMIT)),
+            Staleness {
+                strategy: PriceStrategy::Nearest,
+                max_gap: 60,
+            },
+        );
+        let block = |timestamp| PricedBlock {
+            number: 19134000,
+            timestamp: Some(timestamp),
+        };
+
+        assert_eq!(
+            source.get_price("ETHUSD", block(now - 30)).await.unwrap(),
+            Quote::new(BigDecimal::from_str("2297.12").unwrap(), "kraken").with_timestamp(now - 60)
+        );
+        // the candle of another minute
+        assert!(source.get_price("ETHUSD", block(now - 150)).await.is_err());
+        // older than what Kraken serves, not even requested
+        assert!(source
+            .get_price("ETHUSD", block(now - 86_400))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    async fn test_can_price() {
+        let pairs = vec!["ETHUSDT".to_string(), "ETHUSDC".to_string()];
+
+        let cases = vec![
+            (PriceSourceKind::Binance, "ETHEUR", true),
+            (PriceSourceKind::Okx, "ETHUSDC", true),
+            (PriceSourceKind::Coinbase, "ETHDAI", false), // unknown quote
+            (PriceSourceKind::UniswapV3, "ETHUSDC", true),
+            (PriceSourceKind::UniswapV3, "ETHEUR", false), // no tracked pool
+            (PriceSourceKind::Chainlink, "ETHUSD", false), // not built with it
+            (PriceSourceKind::Fixture, "ETHUSDT", true),
+        ];
+
+        for (kind, pair, expected) in cases {
+            assert_eq!(
+                kind.can_price(pair, &pairs),
+                expected,
+                "Failed for {:?} {}",
+                kind,
+                pair
+            );
+        }
+    }
+
+    /// A venue only listing some of the pairs
+    #[derive(Debug)]
+    struct Listing(&'static [&'static str]);
+
+    #[async_trait]
+    impl PriceSource for Listing {
+        fn name(&self) -> String {
+            "listing".to_string()
+        }
+
+        async fn get_price(&self, pair: &str, _block: PricedBlock) -> Result<Quote> {
+            match self.0.contains(&pair) {
+                true => Ok(Quote::new(BigDecimal::from(2297), "listing")),
+                false => Err(eyre!("HTTP 400 Invalid symbol {}", pair)),
+            }
+        }
+    }
+
+    #[test]
+    async fn test_get_pairs_prices() {
+        let source = Listing(&["ETHUSDT", "ETHEUR"]);
+        let pairs = |pairs: &[&str]| {
+            pairs
+                .iter()
+                .map(|pair| pair.to_string())
+                .collect::<Vec<_>>()
+        };
+        let block = PricedBlock {
+            number: 19134000,
+            timestamp: Some(1706826922),
+        };
+
+        let cases = vec![
+            // (pairs, required, expected priced pairs)
+            (
+                vec!["ETHUSDT", "ETHEUR"],
+                vec!["ETHUSDT"],
+                Some(vec!["ETHEUR", "ETHUSDT"]),
+            ),
+            (
+                vec!["ETHUSDT", "ETHUSD"],
+                vec!["ETHUSDT"],
+                Some(vec!["ETHUSDT"]),
+            ), // a quote currency the venue doesn't list is skipped
+            (vec!["ETHUSDT", "ETHUSDC"], vec!["ETHUSDT", "ETHUSDC"], None), // a pool's pair fails the block
+        ];
+
+        for (all, required, expected) in cases {
+            let priced = get_pairs_prices(&source, &pairs(&all), &pairs(&required), block)
+                .await
+                .ok()
+                .map(|prices| {
+                    let mut priced = prices.into_keys().collect::<Vec<_>>();
+                    priced.sort();
+                    priced
+                });
+            assert_eq!(
+                priced,
+                expected.map(|expected| pairs(&expected)),
+                "Failed for {:?}",
+                all
+            );
+        }
+    }
+
+    #[test]
+    async fn test_venue_symbol() {
+        let cases = vec![
+            ("ETHUSDT", "-", "ETH-USDT"),
+            ("ETHUSD", "-", "ETH-USD"),
+            ("ETHUSDC", "", "ETHUSDC"),
+            ("ETHEUR", "/", "ETH/EUR"),
+            ("ETHDAI", "-", "ETHDAI"), // unknown quote
+            ("USDT", "-", "USDT"),     // no base
+        ];
+
+        for (pair, separator, expected) in cases {
+            assert_eq!(
+                venue_symbol(pair, separator),
+                expected,
+                "Failed for {}",
+                pair
+            );
+        }
+    }
 
     #[test]
     #[ignore]
     async fn test_binance_get_pair_price() {
+        let client = RateLimitedClient::new(BINANCE_RATE_LIMIT);
         let provider = Binance::new("ETHUSDT");
 
-        let price = get_pair_price(&provider, Some(1706826922)).await.unwrap();
-        assert_eq!(price, 2297.12);
+        let price = get_pair_price(&client, &provider, Some(1706826922))
+            .await
+            .unwrap();
+        assert_eq!(price, BigDecimal::from_str("2297.12").unwrap());
 
-        let price = get_pair_price(&provider, Some(1726526911)).await.unwrap();
-        assert_eq!(price, 2282.73);
+        let price = get_pair_price(&client, &provider, Some(1726526911))
+            .await
+            .unwrap();
+        assert_eq!(price, BigDecimal::from_str("2282.73").unwrap());
     }
 }
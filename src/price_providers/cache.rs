@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use eyre::Result;
+use sqlx::PgPool;
+
+use super::{PriceSource, PricedBlock, Quote, Staleness};
+use crate::helpers::{cache_price, get_cached_price};
+
+/// Keeps the historical prices of its source in the `prices` table, so repeated & overlapping jobs
+/// only ever fetch them once. Spot prices (i.e no timestamp) aren't cached.
+/// The prices are kept per `staleness` too, a block in a candle gap is priced differently by each
+#[derive(Debug)]
+pub struct Cached {
+    source: Arc<dyn PriceSource>,
+    db_pool: PgPool,
+    staleness: Staleness,
+}
+
+impl Cached {
+    pub fn new(source: Arc<dyn PriceSource>, db_pool: PgPool, staleness: Staleness) -> Self {
+        Self {
+            source,
+            db_pool,
+            staleness,
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for Cached {
+    fn name(&self) -> String {
+        self.source.name()
+    }
+
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote> {
+        let Some(timestamp) = block.timestamp else {
+            return self.source.get_price(pair, block).await;
+        };
+
+        let (name, staleness) = (self.name(), self.staleness.key());
+        if let Some((price, price_timestamp)) =
+            get_cached_price(&self.db_pool, pair, &name, &staleness, timestamp).await?
+        {
+            return Ok(Quote {
+                timestamp: price_timestamp,
+                ..Quote::new(price, &name)
+            });
+        }
+
+        let quote = self.source.get_price(pair, block).await?;
+        cache_price(&self.db_pool, pair, &name, &staleness, timestamp, &quote).await?;
+        Ok(quote)
+    }
+
+    async fn prefetch(&self, pair: &str, start: i64, end: i64) {
+        self.source.prefetch(pair, start, end).await;
+    }
+
+    fn clear_prefetched(&self) {
+        self.source.clear_prefetched();
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::HashMap, str::FromStr};
+
+use alloy::{
+    eips::BlockId,
+    primitives::{address, Address, I256},
+    providers::RootProvider,
+    pubsub::PubSubFrontend,
+    sol,
+};
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use sqlx::types::BigDecimal;
+use tracing::info;
+
+use super::{PriceSource, PricedBlock, Quote};
+
+/// The (mainnet) aggregator proxies of the pairs we can price, see https://data.chain.link/feeds
+const FEEDS: &[(&str, Address)] = &[(
+    "ETHUSD",
+    address!("5f4eC3Df9cbd43714FE2740f5E3616155c5b8419"),
+)];
+
+sol! {
+    #[sol(rpc)]
+    interface IAggregatorV3 {
+        function decimals() external view returns (uint8);
+        function latestRoundData() external view returns (uint80 roundId, int256 answer, uint256 startedAt, uint256 updatedAt, uint80 answeredInRound);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Feed {
+    address: Address,
+    decimals: u8,
+}
+
+impl Feed {
+    /// The feed's answer as a decimal price, `None` for a non-positive answer
+    fn price(&self, answer: I256) -> Option<BigDecimal> {
+        if answer <= I256::ZERO {
+            return None;
+        }
+        let (digits, _) = BigDecimal::from_str(&answer.to_string())
+            .ok()?
+            .as_bigint_and_exponent();
+        Some(BigDecimal::new(digits, self.decimals as i64))
+    }
+}
+
+/// Prices each pair with its Chainlink aggregator's `latestRoundData`, read at the block itself
+/// (i.e the latest round as of that block), so the price is deterministic per block.
+#[derive(Debug)]
+pub struct Chainlink {
+    provider: RootProvider<PubSubFrontend>,
+    feeds: HashMap<String, Feed>, // pair -> feed
+}
+
+impl Chainlink {
+    pub async fn new(provider: RootProvider<PubSubFrontend>, pairs: &[String]) -> Result<Self> {
+        let mut feeds = HashMap::new();
+        for pair in pairs {
+            let address = FEEDS
+                .iter()
+                .find(|(feed_pair, _)| feed_pair == pair)
+                .map(|(_, address)| *address)
+                .ok_or_else(|| eyre!("No Chainlink feed to price {} with", pair))?;
+            let decimals = IAggregatorV3::new(address, &provider)
+                .decimals()
+                .call()
+                .await?
+                ._0;
+            info!(feed = %address, price_pair = %pair, decimals = decimals, "chainlink price source |");
+
+            feeds.insert(pair.clone(), Feed { address, decimals });
+        }
+
+        Ok(Self { provider, feeds })
+    }
+}
+
+#[async_trait]
+impl PriceSource for Chainlink {
+    fn name(&self) -> String {
+        "chainlink".to_string()
+    }
+
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote> {
+        let feed = self
+            .feeds
+            .get(pair)
+            .ok_or_else(|| eyre!("No Chainlink feed to price {} with", pair))?;
+        let round = IAggregatorV3::new(feed.address, &self.provider)
+            .latestRoundData()
+            .block(BlockId::number(block.number))
+            .call()
+            .await?;
+
+        let price = feed.price(round.answer).ok_or_else(|| {
+            eyre!(
+                "Invalid answer {} from feed {} at block {}",
+                round.answer,
+                feed.address,
+                block.number
+            )
+        })?;
+        Ok(Quote::new(price, &self.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_price() {
+        let feed = Feed {
+            address: Address::ZERO,
+            decimals: 8,
+        };
+
+        let cases = vec![
+            ("229712000000", Some("2297.12")),
+            ("1", Some("0.00000001")),
+            ("0", None),
+            ("-229712000000", None),
+        ];
+
+        for (answer, expected) in cases {
+            assert_eq!(
+                feed.price(I256::from_str(answer).unwrap()),
+                expected.map(|price| BigDecimal::from_str(price).unwrap()),
+                "Failed for {}",
+                answer
+            );
+        }
+    }
+}
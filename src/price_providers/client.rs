@@ -0,0 +1,338 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{eyre, Result};
+use reqwest::{header::HeaderMap, Client, StatusCode};
+use serde_json::Value;
+use tracing::warn;
+
+/// Retries of a request being rate limited (429/418) or failing on the venue's side (5xx)
+const MAX_RETRIES: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// A `Retry-After` longer than this (e.g a 418 IP ban) fails the request instead of stalling it
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+/// Share of the venue's per-minute weight budget we stop at, until the next minute
+const WEIGHT_HEADROOM: f64 = 0.9;
+
+/// How hard a venue lets us hit it
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    pub burst: f64,
+    /// The header reporting the weight used in the current minute, along with the venue's per-minute budget
+    pub weight_header: Option<(&'static str, u64)>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst,
+            refilled_at: Instant::now(),
+            paused_until: None,
+        }
+    }
+
+    /// Takes a token, or returns how long to wait before trying again
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.paused_until {
+            if until > now {
+                return Some(until - now);
+            }
+            self.paused_until = None;
+        }
+
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_second).min(limit.burst);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.requests_per_second,
+            ))
+        }
+    }
+
+    /// Holds off all the requests until `until`
+    fn pause(&mut self, until: Instant) {
+        self.paused_until = Some(self.paused_until.map_or(until, |paused| paused.max(until)));
+    }
+}
+
+/// An HTTP client shared by all the requests to a venue, keeping them within its rate limits.
+///
+/// Requests go through a token bucket, the rate limited (429/418) & failed (5xx) ones are retried
+/// with an exponential backoff (or after the venue's `Retry-After`), which holds off all the others too.
+#[derive(Debug)]
+pub struct RateLimitedClient {
+    client: Client,
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimitedClient {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            // some venues (e.g Coinbase) reject requests without a user agent
+            client: Client::builder()
+                .user_agent(concat!("tx-fees/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .expect("Failed to build the HTTP client"),
+            limit,
+            bucket: Mutex::new(Bucket::new(&limit)),
+        }
+    }
+
+    pub async fn get_json(&self, url: &str) -> Result<Value> {
+        let mut attempt = 0;
+        loop {
+            self.acquire().await;
+
+            let response = self.client.get(url).send().await?;
+            let status = response.status();
+            self.track_weight(response.headers());
+            if status.is_success() {
+                return Ok(response.json::<Value>().await?);
+            }
+
+            let retryable = status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::IM_A_TEAPOT
+                || status.is_server_error();
+            if !retryable || attempt >= MAX_RETRIES {
+                return Err(eyre!("HTTP {} from {}", status, url));
+            }
+
+            let delay = retry_after(response.headers()).unwrap_or_else(|| backoff(attempt));
+            if delay > MAX_RETRY_AFTER {
+                return Err(eyre!(
+                    "HTTP {} from {}, asked to back off for {:?}",
+                    status,
+                    url,
+                    delay
+                ));
+            }
+            warn!(status = %status, url = %url, attempt = attempt + 1, delay = ?delay, "backing off the price venue |");
+            self.pause(delay);
+            attempt += 1;
+        }
+    }
+
+    async fn acquire(&self) {
+        loop {
+            let wait = self
+                .bucket
+                .lock()
+                .expect("Poisoned rate limiter")
+                .take(&self.limit, Instant::now());
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+
+    fn pause(&self, delay: Duration) {
+        self.bucket
+            .lock()
+            .expect("Poisoned rate limiter")
+            .pause(Instant::now() + delay);
+    }
+
+    /// Once the reported weight nears the budget, holds off the requests until the next minute
+    fn track_weight(&self, headers: &HeaderMap) {
+        let Some((header, budget)) = self.limit.weight_header else {
+            return;
+        };
+        let Some(used) = headers
+            .get(header)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+        else {
+            return;
+        };
+
+        if used as f64 >= budget as f64 * WEIGHT_HEADROOM {
+            let delay = until_next_minute();
+            warn!(used_weight = used, budget = budget, delay = ?delay, "price venue weight nearly exhausted |");
+            self.pause(delay);
+        }
+    }
+}
+
+/// `BASE_BACKOFF * 2^attempt`, capped at `MAX_BACKOFF`
+fn backoff(attempt: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(2_u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF)
+}
+
+/// The `Retry-After` header, in seconds
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn until_next_minute() -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Duration::from_secs(60) - Duration::from_secs(now.as_secs() % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const LIMIT: RateLimit = RateLimit {
+        requests_per_second: 2.0,
+        burst: 2.0,
+        weight_header: Some(("x-mbx-used-weight-1m", 100)),
+    };
+
+    #[test]
+    fn test_bucket_take() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: LIMIT.burst,
+            refilled_at: start,
+            paused_until: None,
+        };
+
+        assert_eq!(bucket.take(&LIMIT, start), None);
+        assert_eq!(bucket.take(&LIMIT, start), None);
+        assert_eq!(bucket.take(&LIMIT, start), Some(Duration::from_millis(500)));
+        assert_eq!(
+            bucket.take(&LIMIT, start + Duration::from_millis(500)),
+            None
+        );
+
+        // never refills past the burst
+        assert_eq!(bucket.take(&LIMIT, start + Duration::from_secs(60)), None);
+        assert_eq!(bucket.take(&LIMIT, start + Duration::from_secs(60)), None);
+        assert!(bucket
+            .take(&LIMIT, start + Duration::from_secs(60))
+            .is_some());
+
+        let now = start + Duration::from_secs(120);
+        bucket.pause(now + Duration::from_secs(3));
+        bucket.pause(now + Duration::from_secs(1));
+        assert_eq!(bucket.take(&LIMIT, now), Some(Duration::from_secs(3)));
+        assert_eq!(bucket.take(&LIMIT, now + Duration::from_secs(3)), None);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let cases = vec![
+            (0, Duration::from_millis(500)),
+            (1, Duration::from_secs(1)),
+            (3, Duration::from_secs(4)),
+            (10, MAX_BACKOFF),
+            (u32::MAX, MAX_BACKOFF),
+        ];
+
+        for (attempt, expected) in cases {
+            assert_eq!(backoff(attempt), expected, "Failed for {}", attempt);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_json_retries() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/klines"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/klines"))
+            .respond_with(ResponseTemplate::new(503).insert_header("retry-after", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/klines"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([1])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(LIMIT);
+        let data = client
+            .get_json(&format!("{}/klines", server.uri()))
+            .await
+            .unwrap();
+        assert_eq!(data, serde_json::json!([1]));
+    }
+
+    #[tokio::test]
+    async fn test_get_json_fails() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bad-request"))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/banned"))
+            .respond_with(ResponseTemplate::new(418).insert_header("retry-after", "3600"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(LIMIT);
+        for endpoint in ["bad-request", "banned"] {
+            assert!(
+                client
+                    .get_json(&format!("{}/{}", server.uri(), endpoint))
+                    .await
+                    .is_err(),
+                "Expected an error for {}",
+                endpoint
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_weight() {
+        let client = RateLimitedClient::new(LIMIT);
+        let headers = |used: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-mbx-used-weight-1m", used.parse().unwrap());
+            headers
+        };
+
+        client.track_weight(&headers("50"));
+        assert!(client.bucket.lock().unwrap().paused_until.is_none());
+
+        client.track_weight(&headers("95"));
+        assert!(client.bucket.lock().unwrap().paused_until.is_some());
+    }
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat};
+use serde_json::Value;
+use sqlx::types::BigDecimal;
+
+use super::{minute_start, venue_symbol, PriceProvider, RateLimit};
+
+pub const COINBASE_URL: &str = "https://api.exchange.coinbase.com";
+/// Public endpoints allow 10 requests per second, bursting up to 15
+pub const COINBASE_RATE_LIMIT: RateLimit = RateLimit {
+    requests_per_second: 10.0,
+    burst: 15.0,
+    weight_header: None,
+};
+
+#[derive(Debug)]
+pub struct Coinbase {
+    base_url: String,
+    product: String, // e.g ETH-USDT
+}
+
+impl Coinbase {
+    pub fn new(pair: &str) -> Self {
+        Self::with_base_url(pair, COINBASE_URL)
+    }
+
+    pub fn with_base_url(pair: &str, base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            product: venue_symbol(pair, "-"),
+        }
+    }
+}
+
+impl PriceProvider for Coinbase {
+    fn url(&self, timestamp: Option<i64>) -> String {
+        let base = format!(
+            "{}/products/{}/candles?granularity=60",
+            self.base_url, self.product
+        );
+        match timestamp.and_then(|ts| DateTime::from_timestamp(minute_start(ts), 0)) {
+            Some(start) => {
+                let start = start.to_rfc3339_opts(SecondsFormat::Secs, true);
+                format!("{}&start={}&end={}", base, start, start)
+            }
+            None => base,
+        }
+    }
+
+    fn extract_price(&self, data: &Value, timestamp: Option<i64>) -> Option<BigDecimal> {
+        // newest candle first, [time, low, high, open, close, volume]
+        let mut candles = data.as_array()?.iter().filter_map(Value::as_array);
+        let candle = match timestamp {
+            Some(ts) => candles
+                .find(|candle| candle.first().and_then(Value::as_i64) == Some(minute_start(ts)))?,
+            None => candles.next()?,
+        };
+        // prices are JSON numbers, parsed from their (shortest round-trip) representation
+        BigDecimal::from_str(&candle.get(4)?.as_number()?.to_string()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coinbase() {
+        let provider = Coinbase::new("ETHUSD");
+
+        assert_eq!(
+            provider.url(Some(1706826922)),
+            "https://api.exchange.coinbase.com/products/ETH-USD/candles?granularity=60\
+             &start=2024-02-01T22:35:00Z&end=2024-02-01T22:35:00Z"
+        );
+        let data = serde_json::json!([[1706826900, 2296.5, 2297.9, 2296.61, 2297.12, 104.62]]);
+        let cases = vec![
+            (None, Some("2297.12")),
+            (Some(1706826922), Some("2297.12")),
+            (Some(1706826840), None), // the candle of another minute
+        ];
+
+        for (timestamp, expected) in cases {
+            assert_eq!(
+                provider.extract_price(&data, timestamp),
+                expected.map(|price| BigDecimal::from_str(price).unwrap()),
+                "Failed for {:?}",
+                timestamp
+            );
+        }
+        assert_eq!(provider.extract_price(&serde_json::json!([]), None), None);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+use super::{PriceSource, PricedBlock, Quote};
+
+/// Tries its sources in order, each one given at most `timeout` to answer,
+/// and only fails once all of them have.
+#[derive(Debug)]
+pub struct Fallback {
+    sources: Vec<Arc<dyn PriceSource>>,
+    timeout: Duration,
+}
+
+impl Fallback {
+    pub fn new(sources: Vec<Arc<dyn PriceSource>>, timeout: Duration) -> Self {
+        Self { sources, timeout }
+    }
+}
+
+#[async_trait]
+impl PriceSource for Fallback {
+    fn name(&self) -> String {
+        let names = self
+            .sources
+            .iter()
+            .map(|source| source.name())
+            .collect::<Vec<_>>();
+        format!("fallback({})", names.join(","))
+    }
+
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote> {
+        let mut errors = Vec::new();
+
+        for (attempt, source) in self.sources.iter().enumerate() {
+            let err = match timeout(self.timeout, source.get_price(pair, block)).await {
+                Ok(Ok(quote)) => {
+                    if attempt > 0 {
+                        info!(
+                            source = %source.name(),
+                            pair = %pair,
+                            block_number = block.number,
+                            "priced by fallback source |"
+                        );
+                    }
+                    return Ok(quote);
+                }
+                Ok(Err(err)) => err.to_string(),
+                Err(_) => format!("timed out after {:?}", self.timeout),
+            };
+
+            warn!(
+                source = %source.name(),
+                pair = %pair,
+                block_number = block.number,
+                error = %err,
+                "price source failed |"
+            );
+            errors.push(format!("{}: {}", source.name(), err));
+        }
+
+        Err(eyre!(
+            "All the price sources failed for {} at block {} - {}",
+            pair,
+            block.number,
+            errors.join("; ")
+        ))
+    }
+
+    /// Only the first source is prefetched, the others are only there for when it fails
+    async fn prefetch(&self, pair: &str, start: i64, end: i64) {
+        if let Some(source) = self.sources.first() {
+            source.prefetch(pair, start, end).await;
+        }
+    }
+
+    fn clear_prefetched(&self) {
+        for source in &self.sources {
+            source.clear_prefetched();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::types::BigDecimal;
+
+    #[derive(Debug)]
+    enum Mock {
+        Price(&'static str, i64),
+        Failing(&'static str),
+        Hanging(&'static str),
+    }
+
+    #[async_trait]
+    impl PriceSource for Mock {
+        fn name(&self) -> String {
+            match self {
+                Mock::Price(name, _) | Mock::Failing(name) | Mock::Hanging(name) => {
+                    name.to_string()
+                }
+            }
+        }
+
+        async fn get_price(&self, _pair: &str, _block: PricedBlock) -> Result<Quote> {
+            match self {
+                Mock::Price(name, price) => Ok(Quote::new(BigDecimal::from(*price), name)),
+                Mock::Failing(_) => Err(eyre!("HTTP 429")),
+                Mock::Hanging(_) => {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Err(eyre!("unreachable"))
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let block = PricedBlock {
+            number: 1,
+            timestamp: None,
+        };
+
+        let cases = vec![
+            (
+                vec![Mock::Price("binance", 2297), Mock::Price("okx", 2298)],
+                Some(("binance", 2297)),
+            ),
+            (
+                vec![Mock::Failing("binance"), Mock::Price("okx", 2298)],
+                Some(("okx", 2298)),
+            ),
+            (
+                vec![Mock::Hanging("binance"), Mock::Price("okx", 2298)],
+                Some(("okx", 2298)),
+            ),
+            (vec![Mock::Failing("binance"), Mock::Hanging("okx")], None),
+        ];
+
+        for (sources, expected) in cases {
+            let name = format!("{:?}", sources);
+            let fallback = Fallback::new(
+                sources
+                    .into_iter()
+                    .map(|source| Arc::new(source) as Arc<dyn PriceSource>)
+                    .collect(),
+                Duration::from_millis(50),
+            );
+
+            let quote = fallback.get_price("ETHUSDT", block).await;
+            match expected {
+                Some((source, price)) => assert_eq!(
+                    quote.unwrap(),
+                    Quote::new(BigDecimal::from(price), source),
+                    "Failed for {}",
+                    name
+                ),
+                None => assert!(quote.is_err(), "Expected an error for {}", name),
+            }
+        }
+    }
+}
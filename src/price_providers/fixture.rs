@@ -0,0 +1,223 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::types::BigDecimal;
+use tracing::info;
+
+use super::{PriceSource, PricedBlock, Quote};
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    pair: String,
+    timestamp: i64,
+    price: Value, // either a string or a number
+}
+
+/// Prices from a local file, for running & testing without reaching out to any venue.
+///
+/// The file is either a CSV with a `pair,timestamp,price` header, or (for a `.json` path)
+/// an array of `{"pair": .., "timestamp": .., "price": ..}` objects.
+/// A block is priced with the pair's last price at or before its timestamp, the spot price being the latest one.
+#[derive(Debug)]
+pub struct Fixture {
+    prices: HashMap<String, BTreeMap<i64, BigDecimal>>, // pair -> timestamp -> price
+}
+
+impl Fixture {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| eyre!("Can't read the price fixture {} - {}", path, err))?;
+        let fixture = if path.ends_with(".json") {
+            Self::from_json(&content)?
+        } else {
+            Self::from_csv(&content)?
+        };
+        info!(path = %path, pairs = ?fixture.prices.keys().collect::<Vec<_>>(), "fixture price source |");
+
+        Ok(fixture)
+    }
+
+    fn from_csv(content: &str) -> Result<Self> {
+        let mut lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        let header = lines.next().map(|line| line.replace(' ', ""));
+        if header.as_deref() != Some("pair,timestamp,price") {
+            return Err(eyre!(
+                "The price fixture must start with a pair,timestamp,price header"
+            ));
+        }
+
+        let rows = lines
+            .map(
+                |line| match line.split(',').map(str::trim).collect::<Vec<_>>()[..] {
+                    [pair, timestamp, price] => Ok(Row {
+                        pair: pair.to_string(),
+                        timestamp: timestamp.parse().map_err(|_| {
+                            eyre!("Invalid timestamp in the price fixture: {}", line)
+                        })?,
+                        price: Value::String(price.to_string()),
+                    }),
+                    _ => Err(eyre!("Invalid row in the price fixture: {}", line)),
+                },
+            )
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_rows(rows)
+    }
+
+    fn from_json(content: &str) -> Result<Self> {
+        Self::from_rows(serde_json::from_str(content)?)
+    }
+
+    fn from_rows(rows: Vec<Row>) -> Result<Self> {
+        let mut prices: HashMap<String, BTreeMap<i64, BigDecimal>> = HashMap::new();
+        for row in rows {
+            let price = match &row.price {
+                Value::String(price) => BigDecimal::from_str(price).ok(),
+                Value::Number(price) => BigDecimal::from_str(&price.to_string()).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                eyre!(
+                    "Invalid price {} for {} in the price fixture",
+                    row.price,
+                    row.pair
+                )
+            })?;
+
+            prices
+                .entry(row.pair.to_uppercase())
+                .or_default()
+                .insert(row.timestamp, price);
+        }
+        Ok(Self { prices })
+    }
+}
+
+#[async_trait]
+impl PriceSource for Fixture {
+    fn name(&self) -> String {
+        "fixture".to_string()
+    }
+
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote> {
+        let prices = self
+            .prices
+            .get(pair)
+            .ok_or_else(|| eyre!("No {} prices in the fixture", pair))?;
+        let (timestamp, price) = match block.timestamp {
+            Some(ts) => prices.range(..=ts).next_back(),
+            None => prices.last_key_value(),
+        }
+        .ok_or_else(|| {
+            eyre!(
+                "No {} price in the fixture at block {} ({:?})",
+                pair,
+                block.number,
+                block.timestamp
+            )
+        })?;
+
+        Ok(Quote::new(price.clone(), &self.name()).with_timestamp(*timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(timestamp: Option<i64>) -> PricedBlock {
+        PricedBlock {
+            number: 1,
+            timestamp,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fixture_get_price() {
+        let csv = Fixture::from_csv(
+            "pair,timestamp,price\n\
+             ethusdt,1706826900,2297.12\n\
+             ETHUSDT,1706826960,2298.50\n\
+             ETHUSDC,1706826900,2297.00\n",
+        )
+        .unwrap();
+        let json = Fixture::from_json(
+            r#"[
+                {"pair": "ethusdt", "timestamp": 1706826900, "price": "2297.12"},
+                {"pair": "ETHUSDT", "timestamp": 1706826960, "price": 2298.50},
+                {"pair": "ETHUSDC", "timestamp": 1706826900, "price": 2297}
+            ]"#,
+        )
+        .unwrap();
+
+        let cases = vec![
+            (
+                "ETHUSDT",
+                block(Some(1706826922)),
+                Some(("2297.12", 1706826900)),
+            ),
+            (
+                "ETHUSDT",
+                block(Some(1706826960)),
+                Some(("2298.50", 1706826960)),
+            ),
+            ("ETHUSDT", block(None), Some(("2298.50", 1706826960))),
+            (
+                "ETHUSDC",
+                block(Some(1706827000)),
+                Some(("2297", 1706826900)),
+            ),
+            ("ETHUSDT", block(Some(1706826899)), None),
+            ("ETHDAI", block(None), None),
+        ];
+
+        for fixture in [&csv, &json] {
+            for (pair, block, expected) in &cases {
+                let quote = fixture.get_price(pair, *block).await;
+                match expected {
+                    Some((price, timestamp)) => assert_eq!(
+                        quote.unwrap(),
+                        Quote::new(BigDecimal::from_str(price).unwrap(), "fixture")
+                            .with_timestamp(*timestamp),
+                        "Failed for {} at {:?}",
+                        pair,
+                        block.timestamp
+                    ),
+                    None => assert!(
+                        quote.is_err(),
+                        "Expected an error for {} at {:?}",
+                        pair,
+                        block.timestamp
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fixture_invalid() {
+        let cases = vec![
+            "timestamp,price\n1706826900,2297.12",
+            "pair,timestamp,price\nETHUSDT,1706826900",
+            "pair,timestamp,price\nETHUSDT,yesterday,2297.12",
+            "pair,timestamp,price\nETHUSDT,1706826900,cheap",
+        ];
+
+        for csv in cases {
+            assert!(
+                Fixture::from_csv(csv).is_err(),
+                "Expected an error for {}",
+                csv
+            );
+        }
+    }
+}
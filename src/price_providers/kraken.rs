@@ -0,0 +1,134 @@
+use std::str::FromStr;
+
+use serde_json::Value;
+use sqlx::types::BigDecimal;
+
+use super::{minute_start, PriceProvider, RateLimit};
+
+pub const KRAKEN_URL: &str = "https://api.kraken.com";
+/// OHLC only serves the last 720 candles, whatever the `since`
+const KRAKEN_HISTORY: i64 = 720 * 60;
+/// Public endpoints allow about a request per second
+pub const KRAKEN_RATE_LIMIT: RateLimit = RateLimit {
+    requests_per_second: 1.0,
+    burst: 1.0,
+    weight_header: None,
+};
+
+#[derive(Debug)]
+pub struct Kraken {
+    base_url: String,
+    pair: String, // Kraken takes our pairs as they are, e.g ETHUSDT
+}
+
+impl Kraken {
+    pub fn new(pair: &str) -> Self {
+        Self::with_base_url(pair, KRAKEN_URL)
+    }
+
+    pub fn with_base_url(pair: &str, base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            pair: pair.to_string(),
+        }
+    }
+}
+
+impl PriceProvider for Kraken {
+    fn url(&self, timestamp: Option<i64>) -> String {
+        match timestamp {
+            // `since` is exclusive, the first candle returned is the one `ts` falls in
+            Some(ts) => format!(
+                "{}/0/public/OHLC?pair={}&interval=1&since={}",
+                self.base_url,
+                self.pair,
+                minute_start(ts) - 1
+            ),
+            None => format!("{}/0/public/Ticker?pair={}", self.base_url, self.pair),
+        }
+    }
+
+    fn extract_price(&self, data: &Value, timestamp: Option<i64>) -> Option<BigDecimal> {
+        // the result is keyed by Kraken's own pair name (e.g XETHZUSD), next to OHLC's `last`
+        let (_, result) = data
+            .get("result")?
+            .as_object()?
+            .iter()
+            .find(|(key, _)| *key != "last")?;
+
+        let close = match (result, timestamp) {
+            // OHLC - [time, open, high, low, close, vwap, volume, count], an old `since` gets the latest candles
+            (Value::Array(candles), Some(ts)) => candles
+                .iter()
+                .filter_map(Value::as_array)
+                .find(|candle| candle.first().and_then(Value::as_i64) == Some(minute_start(ts)))?
+                .get(4)?,
+            // Ticker - `c` being the last trade's [price, volume]
+            (_, None) => result.get("c")?.as_array()?.first()?,
+            _ => return None,
+        };
+        BigDecimal::from_str(close.as_str()?).ok()
+    }
+
+    fn history(&self) -> Option<i64> {
+        Some(KRAKEN_HISTORY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kraken() {
+        let provider = Kraken::new("ETHUSD");
+
+        assert_eq!(
+            provider.url(Some(1706826922)),
+            "https://api.kraken.com/0/public/OHLC?pair=ETHUSD&interval=1&since=1706826899"
+        );
+        assert_eq!(
+            provider.url(None),
+            "https://api.kraken.com/0/public/Ticker?pair=ETHUSD"
+        );
+
+        let ohlc = serde_json::json!({"error": [], "result": {
+            "XETHZUSD": [
+                [1706826900, "2296.61", "2297.90", "2296.50", "2297.12", "2297.01", "104.6", 85],
+                [1706826960, "2297.12", "2298.00", "2297.10", "2297.80", "2297.50", "12.3", 20]
+            ],
+            "last": 1706826960
+        }});
+        let cases = vec![
+            (ohlc.clone(), Some(1706826922), Some("2297.12")),
+            (ohlc.clone(), Some(1706826960), Some("2297.80")),
+            (ohlc, Some(1706740522), None), // older than the candles served
+            (
+                serde_json::json!({"error": [], "result": {
+                    "XETHZUSD": {"a": ["2297.13", "1", "1.000"], "c": ["2297.12", "0.5"]}
+                }}),
+                None,
+                Some("2297.12"),
+            ),
+            (
+                serde_json::json!({"error": [], "result": {"XETHZUSD": [], "last": 1706826900}}),
+                Some(1706826922),
+                None,
+            ),
+            (
+                serde_json::json!({"error": ["EQuery:Unknown asset pair"]}),
+                None,
+                None,
+            ),
+        ];
+
+        for (data, timestamp, expected) in cases {
+            assert_eq!(
+                provider.extract_price(&data, timestamp),
+                expected.map(|price| BigDecimal::from_str(price).unwrap()),
+                "Failed for {}",
+                data
+            );
+        }
+    }
+}
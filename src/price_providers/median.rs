@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use futures::future::join_all;
+use sqlx::types::BigDecimal;
+use tracing::warn;
+
+use super::{PriceSource, PricedBlock, Quote};
+
+/// Queries all of its sources concurrently and prices with the median of their answers,
+/// once the ones deviating from it by more than `max_deviation` (relative, e.g 0.01 = 1%) are discarded.
+///
+/// A failing source is skipped, the price only fails if none of them answers (or none of them agrees).
+#[derive(Debug)]
+pub struct Median {
+    sources: Vec<Arc<dyn PriceSource>>,
+    max_deviation: BigDecimal,
+}
+
+impl Median {
+    pub fn new(sources: Vec<Arc<dyn PriceSource>>, max_deviation: BigDecimal) -> Self {
+        Self {
+            sources,
+            max_deviation,
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for Median {
+    fn name(&self) -> String {
+        let names = self
+            .sources
+            .iter()
+            .map(|source| source.name())
+            .collect::<Vec<_>>();
+        format!("median({})", names.join(","))
+    }
+
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote> {
+        let results = join_all(
+            self.sources
+                .iter()
+                .map(|source| source.get_price(pair, block)),
+        )
+        .await;
+
+        let mut quotes = Vec::new();
+        for (source, result) in self.sources.iter().zip(results) {
+            match result {
+                Ok(quote) => quotes.push(quote),
+                Err(err) => warn!(
+                    source = %source.name(),
+                    pair = %pair,
+                    block_number = block.number,
+                    error = %err,
+                    "price source failed |"
+                ),
+            }
+        }
+
+        aggregate(quotes, &self.max_deviation, block.timestamp).ok_or_else(|| {
+            eyre!(
+                "No agreeing price from {} for {} at block {}",
+                self.name(),
+                pair,
+                block.number
+            )
+        })
+    }
+
+    async fn prefetch(&self, pair: &str, start: i64, end: i64) {
+        join_all(
+            self.sources
+                .iter()
+                .map(|source| source.prefetch(pair, start, end)),
+        )
+        .await;
+    }
+
+    fn clear_prefetched(&self) {
+        for source in &self.sources {
+            source.clear_prefetched();
+        }
+    }
+}
+
+/// The median of the quotes' prices, after dropping the outliers.
+/// Observed at the stalest of the kept quotes, relative to the requested `timestamp`
+fn aggregate(
+    quotes: Vec<Quote>,
+    max_deviation: &BigDecimal,
+    timestamp: Option<i64>,
+) -> Option<Quote> {
+    let all_median = median(quotes.iter().map(|quote| quote.price.clone()).collect())?;
+    let tolerance = &all_median * max_deviation;
+
+    let (kept, outliers): (Vec<_>, Vec<_>) = quotes
+        .into_iter()
+        .partition(|quote| (&quote.price - &all_median).abs() <= tolerance);
+    for outlier in &outliers {
+        warn!(
+            sources = ?outlier.sources,
+            price = %outlier.price,
+            median = %all_median,
+            "discarded outlier price |"
+        );
+    }
+
+    Some(Quote {
+        price: median(kept.iter().map(|quote| quote.price.clone()).collect())?,
+        timestamp: timestamp.and_then(|requested| {
+            kept.iter()
+                .filter_map(|quote| quote.timestamp)
+                .max_by_key(|observed| (observed - requested).abs())
+        }),
+        sources: kept.into_iter().flat_map(|quote| quote.sources).collect(),
+    })
+}
+
+fn median(mut prices: Vec<BigDecimal>) -> Option<BigDecimal> {
+    prices.sort();
+    let mid = prices.len() / 2;
+    match prices.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(prices[mid].clone()),
+        _ => Some((&prices[mid - 1] + &prices[mid]) / BigDecimal::from(2)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_aggregate() {
+        let quotes = |prices: &[(&str, &str)]| {
+            prices
+                .iter()
+                .map(|(source, price)| Quote::new(BigDecimal::from_str(price).unwrap(), source))
+                .collect::<Vec<_>>()
+        };
+
+        let cases = vec![
+            (
+                quotes(&[
+                    ("binance", "2297.12"),
+                    ("coinbase", "2297.50"),
+                    ("okx", "2296.90"),
+                ]),
+                Some(("2297.12", vec!["binance", "coinbase", "okx"])),
+            ),
+            (
+                // a wick
+                quotes(&[
+                    ("binance", "2297.12"),
+                    ("coinbase", "2297.50"),
+                    ("okx", "2100"),
+                ]),
+                Some(("2297.31", vec!["binance", "coinbase"])),
+            ),
+            (
+                quotes(&[("binance", "2297.12"), ("coinbase", "2297.50")]),
+                Some(("2297.31", vec!["binance", "coinbase"])),
+            ),
+            (
+                // no agreement
+                quotes(&[("binance", "2297.12"), ("coinbase", "2400")]),
+                None,
+            ),
+            (
+                quotes(&[("binance", "2297.12")]),
+                Some(("2297.12", vec!["binance"])),
+            ),
+            (quotes(&[]), None),
+        ];
+
+        for (quotes, expected) in cases {
+            let expected = expected.map(|(price, sources)| Quote {
+                price: BigDecimal::from_str(price).unwrap(),
+                sources: sources.into_iter().map(String::from).collect(),
+                timestamp: None,
+            });
+            assert_eq!(
+                aggregate(quotes.clone(), &BigDecimal::from_str("0.01").unwrap(), None),
+                expected,
+                "Failed for {:?}",
+                quotes
+            );
+        }
+    }
+
+    #[test]
+    fn test_aggregate_timestamp() {
+        let quotes = vec![
+            Quote::new(BigDecimal::from(2297), "binance").with_timestamp(1706826922),
+            Quote::new(BigDecimal::from(2298), "coinbase").with_timestamp(1706826900),
+            Quote::new(BigDecimal::from(2100), "okx").with_timestamp(1706826800), // outlier
+            Quote::new(BigDecimal::from(2297), "uniswap-v3"),                     // at the block
+        ];
+        let max_deviation = BigDecimal::from_str("0.01").unwrap();
+
+        let quote = aggregate(quotes.clone(), &max_deviation, Some(1706826922)).unwrap();
+        assert_eq!(quote.timestamp, Some(1706826900));
+        let quote = aggregate(quotes, &max_deviation, None).unwrap();
+        assert_eq!(quote.timestamp, None);
+    }
+}
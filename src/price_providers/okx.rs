@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use serde_json::Value;
+use sqlx::types::BigDecimal;
+
+use super::{minute_start, venue_symbol, PriceProvider, RateLimit};
+
+pub const OKX_URL: &str = "https://www.okx.com";
+/// History candles allow 20 requests per 2 seconds
+pub const OKX_RATE_LIMIT: RateLimit = RateLimit {
+    requests_per_second: 10.0,
+    burst: 10.0,
+    weight_header: None,
+};
+
+#[derive(Debug)]
+pub struct Okx {
+    base_url: String,
+    instrument: String, // e.g ETH-USDT
+}
+
+impl Okx {
+    pub fn new(pair: &str) -> Self {
+        Self::with_base_url(pair, OKX_URL)
+    }
+
+    pub fn with_base_url(pair: &str, base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            instrument: venue_symbol(pair, "-"),
+        }
+    }
+}
+
+impl PriceProvider for Okx {
+    fn url(&self, timestamp: Option<i64>) -> String {
+        match timestamp {
+            // `after` returns the candles opened before it, i.e the first one is the candle `ts` falls in
+            Some(ts) => format!(
+                "{}/api/v5/market/history-candles?instId={}&bar=1m&limit=1&after={}",
+                self.base_url,
+                self.instrument,
+                (minute_start(ts) + 60) * 1000
+            ),
+            None => format!(
+                "{}/api/v5/market/candles?instId={}&bar=1m&limit=1",
+                self.base_url, self.instrument
+            ),
+        }
+    }
+
+    fn extract_price(&self, data: &Value, timestamp: Option<i64>) -> Option<BigDecimal> {
+        // [ts, open, high, low, close, volume, ...], all string encoded
+        let candle = data.get("data")?.as_array()?.first()?.as_array()?;
+        let open_time = candle.first()?.as_str()?.parse::<i64>().ok()?;
+        if timestamp.is_some_and(|ts| open_time != minute_start(ts) * 1000) {
+            return None;
+        }
+        BigDecimal::from_str(candle.get(4)?.as_str()?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_okx() {
+        let provider = Okx::new("ETHUSDT");
+
+        assert_eq!(
+            provider.url(Some(1706826922)),
+            "https://www.okx.com/api/v5/market/history-candles?instId=ETH-USDT&bar=1m&limit=1&after=1706826960000"
+        );
+        let data = serde_json::json!({"code": "0", "msg": "", "data": [[
+            "1706826900000", "2296.61", "2297.9", "2296.5", "2297.12", "104.6", "240271.1", "240271.1", "1"
+        ]]});
+        let cases = vec![
+            (None, Some("2297.12")),
+            (Some(1706826922), Some("2297.12")),
+            (Some(1706826990), None), // the candle of another minute
+        ];
+
+        for (timestamp, expected) in cases {
+            assert_eq!(
+                provider.extract_price(&data, timestamp),
+                expected.map(|price| BigDecimal::from_str(price).unwrap()),
+                "Failed for {:?}",
+                timestamp
+            );
+        }
+        assert_eq!(
+            provider.extract_price(
+                &serde_json::json!({"code": "51001", "msg": "Instrument ID does not exist", "data": []}),
+                None
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::{collections::HashMap, str::FromStr};
+
+use alloy::{
+    eips::BlockId,
+    primitives::{address, Address, U160, U256},
+    providers::RootProvider,
+    pubsub::PubSubFrontend,
+    sol,
+};
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use sqlx::types::BigDecimal;
+use tracing::info;
+
+use super::{PriceSource, PricedBlock, Quote};
+use crate::configs::PoolConfig;
+
+const WETH: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+/// Decimal places the on-chain prices are rounded to (half to even)
+const PRICE_SCALE: i64 = 18;
+
+sol! {
+    #[sol(rpc)]
+    interface IUniswapV3Pool {
+        function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked);
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+    }
+
+    #[sol(rpc)]
+    interface IERC20 {
+        function decimals() external view returns (uint8);
+    }
+}
+
+/// A WETH pool, along with what's needed to turn its `sqrtPriceX96` into an ETH price
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct WethPool {
+    address: Address,
+    weth_is_token0: bool,
+    token0_decimals: u8,
+    token1_decimals: u8,
+}
+
+impl WethPool {
+    async fn load(provider: &RootProvider<PubSubFrontend>, address: Address) -> Result<Self> {
+        let pool = IUniswapV3Pool::new(address, provider);
+        let token0 = pool.token0().call().await?._0;
+        let token1 = pool.token1().call().await?._0;
+        if token0 != WETH && token1 != WETH {
+            return Err(eyre!("Pool {} has no WETH side", address));
+        }
+
+        Ok(Self {
+            address,
+            weth_is_token0: token0 == WETH,
+            token0_decimals: IERC20::new(token0, provider).decimals().call().await?._0,
+            token1_decimals: IERC20::new(token1, provider).decimals().call().await?._0,
+        })
+    }
+
+    /// ETH's price in the pool's other token, `None` for an uninitialised pool.
+    ///
+    /// `sqrtPriceX96` is `sqrt(token1 / token0) * 2^96`, in the tokens' smallest units
+    fn eth_price(&self, sqrt_price_x96: U160) -> Option<BigDecimal> {
+        if sqrt_price_x96.is_zero() {
+            return None;
+        }
+        let sqrt_price = BigDecimal::from_str(&sqrt_price_x96.to_string()).ok()?;
+        let q192 = BigDecimal::from_str(&(U256::from(1) << 192_usize).to_string()).ok()?;
+        let decimals_diff = self.token0_decimals as i64 - self.token1_decimals as i64;
+
+        let price = if self.weth_is_token0 {
+            shift(&(&sqrt_price * &sqrt_price / q192), decimals_diff)
+        } else {
+            shift(&(q192 / (&sqrt_price * &sqrt_price)), -decimals_diff)
+        };
+        Some(price.round(PRICE_SCALE))
+    }
+}
+
+/// `value * 10^exponent`
+fn shift(value: &BigDecimal, exponent: i64) -> BigDecimal {
+    let (digits, scale) = value.as_bigint_and_exponent();
+    BigDecimal::new(digits, scale - exponent)
+}
+
+/// Prices each pair with the `slot0` of the (first) tracked pool priced with it, read at the block itself.
+/// Block-accurate & reproducible, without going through any external service.
+#[derive(Debug)]
+pub struct UniswapV3 {
+    provider: RootProvider<PubSubFrontend>,
+    pools: HashMap<String, WethPool>, // pair -> pool
+}
+
+impl UniswapV3 {
+    pub async fn new(
+        provider: RootProvider<PubSubFrontend>,
+        pools: &[PoolConfig],
+        pairs: &[String],
+    ) -> Result<Self> {
+        let mut weth_pools = HashMap::new();
+        for pool in pools {
+            if !weth_pools.contains_key(&pool.price_pair) {
+                let weth_pool = WethPool::load(&provider, pool.address).await?;
+                info!(pool = %pool.address, price_pair = %pool.price_pair, ?weth_pool, "on-chain price source |");
+                weth_pools.insert(pool.price_pair.clone(), weth_pool);
+            }
+        }
+        if let Some(pair) = pairs.iter().find(|pair| !weth_pools.contains_key(*pair)) {
+            return Err(eyre!("No tracked pool to price {} with", pair));
+        }
+
+        Ok(Self {
+            provider,
+            pools: weth_pools,
+        })
+    }
+}
+
+#[async_trait]
+impl PriceSource for UniswapV3 {
+    fn name(&self) -> String {
+        "uniswap-v3".to_string()
+    }
+
+    async fn get_price(&self, pair: &str, block: PricedBlock) -> Result<Quote> {
+        let pool = self
+            .pools
+            .get(pair)
+            .ok_or_else(|| eyre!("No tracked pool to price {} with", pair))?;
+        let slot0 = IUniswapV3Pool::new(pool.address, &self.provider)
+            .slot0()
+            .block(BlockId::number(block.number))
+            .call()
+            .await?;
+
+        let price = pool
+            .eth_price(slot0.sqrtPriceX96)
+            .ok_or_else(|| eyre!("Pool {} isn't initialised", pool.address))?;
+        Ok(Quote::new(price, &self.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eth_price() {
+        let pool = |weth_is_token0, token0_decimals, token1_decimals| WethPool {
+            address: Address::ZERO,
+            weth_is_token0,
+            token0_decimals,
+            token1_decimals,
+        };
+
+        let cases = vec![
+            (
+                pool(false, 6, 18), // USDC/WETH
+                "1771595571142957166518320255467520",
+                Some("1999.999999999999856497"),
+            ),
+            (
+                pool(true, 18, 6), // WETH/USDT
+                "3543191142286914205922034",
+                Some("2000.000000001128925830"),
+            ),
+            (pool(true, 18, 6), "0", None), // uninitialised
+        ];
+
+        for (pool, sqrt_price_x96, expected) in cases {
+            assert_eq!(
+                pool.eth_price(U160::from_str(sqrt_price_x96).unwrap()),
+                expected.map(|price| BigDecimal::from_str(price).unwrap()),
+                "Failed for {}",
+                sqrt_price_x96
+            );
+        }
+    }
+}
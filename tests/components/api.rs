@@ -11,20 +11,28 @@ lazy_static::lazy_static! {
 
 async fn insert_mock_data(db_pool: &PgPool) {
     sqlx::query!(
-        "INSERT INTO blocks (hash, number, eth_usdt) VALUES ($1, $2, $3)",
+        "INSERT INTO blocks (hash, number, eth_usdt, price_sources, base_fee_per_gas, blob_gas_used, excess_blob_gas)
+         VALUES ($1, $2, 3500.12, '{binance,coinbase}', 19000000000, 131072, 0)",
         "0x7D0AA91b12d31755D2fc99d22e09947936E00474",
         123456,
-        3500.0
     )
     .execute(db_pool)
     .await
     .expect("Failed to insert mock block");
 
     sqlx::query!(
-        "INSERT INTO txs (hash, block_hash, fee_usdt) VALUES ($1, $2, $3)",
+        "INSERT INTO block_prices (block_hash, pair, price, sources) VALUES ($1, 'ETHUSDT', 3500.12, '{binance,coinbase}')",
+        "0x7D0AA91b12d31755D2fc99d22e09947936E00474",
+    )
+    .execute(db_pool)
+    .await
+    .expect("Failed to insert mock block price");
+
+    sqlx::query!(
+        "INSERT INTO txs (hash, block_hash, pool_address, price_pair, fee_usdt, effective_gas_price, gas_used, fee_wei, base_fee_per_gas, priority_fee_per_gas, burnt_fee_wei, priority_fee_wei, blob_gas_used, blob_gas_price, blob_fee_wei)
+         VALUES ($1, $2, '0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640', 'ETHUSDT', 15.00092305172864, 20000000000, 214285, 4285831072000000, 19000000000, 1000000000, 4071415000000000, 214285000000000, 131072, 1000000000, 131072000000000)",
         "0xc0dc5948835b50337e8548dc7518dafd3f65b12b1e5f381b7f16684124924a54",
         "0x7D0AA91b12d31755D2fc99d22e09947936E00474",
-        15.0
     )
     .execute(db_pool)
     .await
@@ -52,8 +60,228 @@ async fn test_get_fee_success() {
         "0x7D0AA91b12d31755D2fc99d22e09947936E00474"
     );
     assert_eq!(body["block_number"], 123456);
-    assert_eq!(body["fee_usdt"], 15.0);
-    assert_eq!(body["eth_usdt_ratio"], 3500.0);
+    assert_eq!(
+        body["pool_address"],
+        "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640"
+    );
+    assert_eq!(body["price_pair"], "ETHUSDT");
+    assert_eq!(body["fee_quote"], "15.00092305172864");
+    assert_eq!(body["eth_quote_ratio"], "3500.12");
+    assert_eq!(body["price_sources"], json!(["binance", "coinbase"]));
+    assert_eq!(body["status"], "pending");
+    assert_eq!(body["effective_gas_price"], "20000000000");
+    assert_eq!(body["gas_used"], "214285");
+    assert_eq!(body["fee_wei"], "4285831072000000");
+    assert_eq!(body["base_fee_per_gas"], "19000000000");
+    assert_eq!(body["priority_fee_per_gas"], "1000000000");
+    assert_eq!(body["breakdown"]["burnt_fee_wei"], "4071415000000000");
+    assert_eq!(body["breakdown"]["priority_fee_wei"], "214285000000000");
+    assert_eq!(body["breakdown"]["burnt_fee_quote"], "14.2504410698");
+    assert_eq!(body["breakdown"]["priority_fee_quote"], "0.7500232142");
+    assert_eq!(body["blob_gas_used"], "131072");
+    assert_eq!(body["blob_gas_price"], "1000000000");
+    assert_eq!(body["blob_fee_wei"], "131072000000000");
+    assert_eq!(body["block_blob_gas_used"], "131072");
+    assert_eq!(body["block_excess_blob_gas"], "0");
+    assert_eq!(body["breakdown"]["blob_fee_wei"], "131072000000000");
+    assert_eq!(body["breakdown"]["blob_fee_quote"], "0.45876772864");
+
+    teardown_test_db(app).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_fee_pool_filter() {
+    let app = spawn_test_server().await;
+    insert_mock_data(&app.db_pool).await;
+
+    let tx_hash = "0xc0dc5948835b50337e8548dc7518dafd3f65b12b1e5f381b7f16684124924a54";
+    let cases = vec![
+        ("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640", 200), // the tx's pool, case insensitive
+        ("0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8", 404), // another pool
+        ("0x8ad599c3a0ff1de082011efddc58f1908eb6e6", 400),   // invalid address
+    ];
+
+    for (pool, expected_status) in cases {
+        let response = CLIENT
+            .get(format!("{}/v1/fees/{tx_hash}?pool={pool}", &app.address))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(response.status(), expected_status, "Failed for {}", pool);
+    }
+
+    teardown_test_db(app).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_fee_currency() {
+    let app = spawn_test_server().await;
+    insert_mock_data(&app.db_pool).await;
+
+    sqlx::query!(
+        "INSERT INTO block_prices (block_hash, pair, price, sources) VALUES ($1, 'ETHEUR', 3200.5, '{binance}')",
+        "0x7D0AA91b12d31755D2fc99d22e09947936E00474",
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert mock block price");
+
+    let tx_hash = "0xc0dc5948835b50337e8548dc7518dafd3f65b12b1e5f381b7f16684124924a54";
+    let response = CLIENT
+        .get(format!("{}/v1/fees/{tx_hash}?currency=eur", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse JSON");
+    assert_eq!(body["fee_quote"], "15.00092305172864");
+    assert_eq!(body["currency_fee"]["currency"], "EUR");
+    assert_eq!(body["currency_fee"]["price_pair"], "ETHEUR");
+    assert_eq!(body["currency_fee"]["eth_price"], "3200.5");
+    assert_eq!(body["currency_fee"]["fee"], "13.716802345936");
+    assert_eq!(body["currency_fee"]["price_sources"], json!(["binance"]));
+
+    let cases = vec![
+        ("", 200),    // no currency requested
+        ("GBP", 404), // not priced in GBP
+        ("EUR1", 400),
+    ];
+    for (currency, expected_status) in cases {
+        let url = match currency {
+            "" => format!("{}/v1/fees/{tx_hash}", &app.address),
+            _ => format!("{}/v1/fees/{tx_hash}?currency={currency}", &app.address),
+        };
+        let response = CLIENT
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(
+            response.status(),
+            expected_status,
+            "Failed for {}",
+            currency
+        );
+        if expected_status == 200 {
+            let body: serde_json::Value = response.json().await.expect("Failed to parse JSON");
+            assert_eq!(body["currency_fee"], serde_json::Value::Null);
+        }
+    }
+
+    teardown_test_db(app).await.unwrap();
+}
+
+async fn get_fee(address: &str, tx_hash: &str) -> serde_json::Value {
+    let response = CLIENT
+        .get(format!("{}/v1/fees/{tx_hash}", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), 200, "Failed for {}", tx_hash);
+    response.json().await.expect("Failed to parse JSON")
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_fee_legacy_tx() {
+    let app = spawn_test_server().await;
+    insert_mock_data(&app.db_pool).await;
+
+    // stored before the raw values were persisted
+    let tx_hash = "0x1c0f1a4f2f3d1c6b0e8f2b9d4a7c3e5f6a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d";
+    sqlx::query!(
+        "INSERT INTO txs (hash, block_hash, pool_address, price_pair, fee_usdt)
+         VALUES ($1, $2, '0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640', 'ETHUSDT', 15.00092305172864)",
+        tx_hash,
+        "0x7D0AA91b12d31755D2fc99d22e09947936E00474",
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert mock transaction");
+
+    let body = get_fee(&app.address, tx_hash).await;
+    assert_eq!(body["fee_quote"], "15.00092305172864");
+    for field in [
+        "effective_gas_price",
+        "gas_used",
+        "fee_wei",
+        "base_fee_per_gas",
+        "priority_fee_per_gas",
+    ] {
+        assert_eq!(body[field], serde_json::Value::Null, "Failed for {}", field);
+    }
+
+    teardown_test_db(app).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_fee_pre_london_tx() {
+    let app = spawn_test_server().await;
+
+    // no base fee to split the fee into its burnt & priority parts
+    let (block_hash, tx_hash) = (
+        "0x2b8e0e5f9c1d4a3b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b",
+        "0x3d5f7a9b1c2e4f6a8b0c1d3e5f7a9b0c2d4e6f8a0b1c3d5e7f9a1b2c4d6e8f0a",
+    );
+    sqlx::query!(
+        "INSERT INTO blocks (hash, number, eth_usdt) VALUES ($1, 12000000, 2000)",
+        block_hash,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert mock block");
+    sqlx::query!(
+        "INSERT INTO txs (hash, block_hash, pool_address, price_pair, fee_usdt, effective_gas_price, gas_used, fee_wei)
+         VALUES ($1, $2, '0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640', 'ETHUSDT', 4.2, 100000000000, 21000, 2100000000000000)",
+        tx_hash,
+        block_hash,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert mock transaction");
+
+    let body = get_fee(&app.address, tx_hash).await;
+    assert_eq!(body["fee_quote"], "4.2");
+    assert_eq!(body["fee_wei"], "2100000000000000");
+    assert_eq!(body["base_fee_per_gas"], serde_json::Value::Null);
+    assert_eq!(body["priority_fee_per_gas"], serde_json::Value::Null);
+    assert_eq!(body["breakdown"], serde_json::Value::Null);
+
+    teardown_test_db(app).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_fee_non_blob_tx() {
+    let app = spawn_test_server().await;
+    insert_mock_data(&app.db_pool).await;
+
+    let tx_hash = "0x4e6a8c0b2d4f6a8c0e2b4d6f8a0c2e4b6d8f0a2c4e6b8d0f2a4c6e8b0d2f4a6c";
+    sqlx::query!(
+        "INSERT INTO txs (hash, block_hash, pool_address, price_pair, fee_usdt, effective_gas_price, gas_used, fee_wei, base_fee_per_gas, priority_fee_per_gas, burnt_fee_wei, priority_fee_wei)
+         VALUES ($1, $2, '0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640', 'ETHUSDT', 1.4700504, 20000000000, 21000, 420000000000000, 19000000000, 1000000000, 399000000000000, 21000000000000)",
+        tx_hash,
+        "0x7D0AA91b12d31755D2fc99d22e09947936E00474",
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert mock transaction");
+
+    let body = get_fee(&app.address, tx_hash).await;
+    assert_eq!(body["fee_wei"], "420000000000000");
+    assert_eq!(body["breakdown"]["burnt_fee_wei"], "399000000000000");
+    for field in ["blob_gas_used", "blob_gas_price", "blob_fee_wei"] {
+        assert_eq!(body[field], serde_json::Value::Null, "Failed for {}", field);
+    }
+    assert_eq!(body["breakdown"]["blob_fee_wei"], serde_json::Value::Null);
+    assert_eq!(body["breakdown"]["blob_fee_quote"], serde_json::Value::Null);
 
     teardown_test_db(app).await.unwrap();
 }
@@ -120,6 +348,7 @@ async fn test_job_success() {
     assert_eq!(job.start_time, 1514764800);
     assert_eq!(job.end_time, 1514851200);
     assert_eq!(job.status, "pending");
+    assert_eq!(job.price_pair, None); // the job executor's configured pair
 
     // verify the redis message queue entry is present and equal to the expected value
     let mut conn = app
@@ -227,6 +456,30 @@ async fn test_job_malformed_request() {
             "missing start_time",
         ),
         (json!({}), "empty request"),
+        (
+            json!({
+                "start_time": 1514764800_i64,
+                "end_time": 1514851200_i64,
+                "price_pair": 1
+            }),
+            "invalid price_pair type",
+        ),
+        (
+            json!({
+                "start_time": 1514764800_i64,
+                "end_time": 1514851200_i64,
+                "price_pair": "ETH/USDT"
+            }),
+            "invalid price_pair",
+        ),
+        (
+            json!({
+                "start_time": 1514764800_i64,
+                "end_time": 1514851200_i64,
+                "price_pair": "ETHEUR"
+            }),
+            "unsupported price_pair",
+        ),
     ];
 
     for (request, test_case) in test_cases {
@@ -256,7 +509,8 @@ async fn test_get_job_status() {
     // First create a job
     let request = json!({
         "start_time": 1514764800,
-        "end_time": 1514851200
+        "end_time": 1514851200,
+        "price_pair": "ethusdc"
     });
 
     let response = CLIENT
@@ -285,8 +539,36 @@ async fn test_get_job_status() {
     let body: serde_json::Value = response.json().await.expect("Failed to parse JSON");
     assert_eq!(body["job_id"], job_id);
     assert_eq!(body["status"], "pending");
+    assert_eq!(body["price_pair"], "ETHUSDC");
     assert_eq!(body["start_time"], 1514764800);
     assert_eq!(body["end_time"], 1514851200);
+    assert_eq!(body["attempts"], 0);
+    assert_eq!(body["error"], serde_json::Value::Null);
+
+    // a job that's out of retries
+    sqlx::query!(
+        "UPDATE batch_jobs SET status = 'failed', attempts = 4, error = 'No ETHUSDC price within 60s of 1514764800 (Nearest)' WHERE id = $1",
+        job_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to update job");
+
+    let response = CLIENT
+        .get(format!("{}/v1/jobs/{}", &app.address, job_id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), 200);
+
+    let body: serde_json::Value = response.json().await.expect("Failed to parse JSON");
+    assert_eq!(body["status"], "failed");
+    assert_eq!(body["attempts"], 4);
+    assert_eq!(
+        body["error"],
+        "No ETHUSDC price within 60s of 1514764800 (Nearest)"
+    );
 
     // Test non-existent job
     let response = CLIENT
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use alloy::{
+    primitives::{BlockHash, B256},
+    rpc::types::Header,
+};
+use async_trait::async_trait;
+use serial_test::serial;
+use sqlx::{types::BigDecimal, PgPool};
+use tx_fees::{
+    components::fee_tracker::{reconcile_stored_blocks, CanonicalChain, MAX_REORG_DEPTH},
+    helpers::{store_block, store_tracked_block_number, tracked_block_number},
+    price_providers::Quote,
+};
+
+use crate::utils::{spawn_test_server, teardown_test_db};
+
+/// `len` headers from `start` on, each one the parent of the next. `fork` tells apart the chains at the same heights
+fn chain(start: u64, len: u64, parent_hash: BlockHash, fork: u8) -> Vec<Header> {
+    let mut headers: Vec<Header> = Vec::new();
+    for number in start..start + len {
+        let parent_hash = headers.last().map_or(parent_hash, |parent| parent.hash);
+        headers.push(Header::new(alloy::consensus::Header {
+            number,
+            parent_hash,
+            timestamp: 1706826922 + number,
+            extra_data: vec![fork].into(),
+            ..Default::default()
+        }));
+    }
+    headers
+}
+
+fn hashes<'a>(headers: impl IntoIterator<Item = &'a Header>) -> Vec<String> {
+    headers
+        .into_iter()
+        .map(|header| header.hash.to_string())
+        .collect()
+}
+
+/// The canonical chain, as the RPC serves it
+struct Canonical(Vec<Header>);
+
+#[async_trait]
+impl CanonicalChain for Canonical {
+    async fn header_by_hash(&self, block_hash: BlockHash) -> eyre::Result<Header> {
+        self.0
+            .iter()
+            .find(|header| header.hash == block_hash)
+            .cloned()
+            .ok_or_else(|| eyre::eyre!("Block {} not found", block_hash))
+    }
+
+    async fn header_by_number(&self, block_number: u64) -> eyre::Result<Header> {
+        self.0
+            .iter()
+            .find(|header| header.number == block_number)
+            .cloned()
+            .ok_or_else(|| eyre::eyre!("Block {} not found", block_number))
+    }
+}
+
+async fn store_headers(db_pool: &PgPool, headers: &[Header]) {
+    let prices = HashMap::from([(
+        "ETHUSDT".to_string(),
+        Quote::new(BigDecimal::from(3500), "binance"),
+    )]);
+    for header in headers {
+        store_block(db_pool, header, "ETHUSDT", &prices)
+            .await
+            .unwrap();
+    }
+}
+
+async fn stored_hashes(db_pool: &PgPool) -> Vec<String> {
+    sqlx::query_scalar!("SELECT hash FROM blocks ORDER BY number")
+        .fetch_all(db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn test_reconcile_stored_blocks() {
+    let app = spawn_test_server().await;
+    let canonical = chain(100, 10, B256::ZERO, 0);
+    let rpc = Canonical(canonical.clone());
+    // a fork off 104 that got stored before the chain moved on without it
+    let fork = chain(105, 3, canonical[4].hash, 1);
+
+    store_headers(&app.db_pool, &canonical[..=4]).await;
+
+    // the stored blocks are the new block's ancestors, nothing to reconcile
+    let reorg = reconcile_stored_blocks(&app.db_pool, &rpc, &canonical[5], MAX_REORG_DEPTH)
+        .await
+        .unwrap();
+    assert!(reorg.orphaned.is_empty());
+    assert!(reorg.canonical.is_empty());
+    assert_eq!(stored_hashes(&app.db_pool).await, hashes(&canonical[..=4]));
+
+    // the walk back over the orphaned blocks is bound by the max depth...
+    store_headers(&app.db_pool, &fork).await;
+    let reorg = reconcile_stored_blocks(&app.db_pool, &rpc, &canonical[8], 2)
+        .await
+        .unwrap();
+    assert_eq!(reorg.orphaned, hashes(fork[1..].iter().rev()));
+    assert_eq!(
+        hashes(&reorg.canonical),
+        hashes(canonical[6..=7].iter().rev())
+    );
+    assert_eq!(
+        stored_hashes(&app.db_pool).await,
+        hashes(canonical[..=4].iter().chain(&fork[..1]))
+    );
+
+    // ...and carries on with the next block, past the gap left by the canonical blocks that aren't stored yet
+    let reorg = reconcile_stored_blocks(&app.db_pool, &rpc, &canonical[8], MAX_REORG_DEPTH)
+        .await
+        .unwrap();
+    assert_eq!(reorg.orphaned, hashes(&fork[..1]));
+    assert_eq!(hashes(&reorg.canonical), hashes(&canonical[5..=5]));
+    assert_eq!(stored_hashes(&app.db_pool).await, hashes(&canonical[..=4]));
+
+    // an orphaned block at the same height as the new block, with a canonical parent
+    let sibling = chain(105, 1, canonical[4].hash, 2);
+    store_headers(&app.db_pool, &sibling).await;
+    let reorg = reconcile_stored_blocks(&app.db_pool, &rpc, &canonical[5], MAX_REORG_DEPTH)
+        .await
+        .unwrap();
+    assert_eq!(reorg.orphaned, hashes(&sibling));
+    assert!(reorg.canonical.is_empty());
+    assert_eq!(stored_hashes(&app.db_pool).await, hashes(&canonical[..=4]));
+
+    teardown_test_db(app).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_tracked_block_number() {
+    let app = spawn_test_server().await;
+    assert_eq!(tracked_block_number(&app.db_pool).await.unwrap(), None);
+
+    // the blocks stored by a job (ahead of the tracker) aren't where the tracker's backfill starts from
+    store_headers(&app.db_pool, &chain(21_000_000, 2, B256::ZERO, 0)).await;
+    assert_eq!(tracked_block_number(&app.db_pool).await.unwrap(), None);
+
+    let cases = vec![
+        // (stored, expected)
+        (19_000_000, 19_000_000),
+        (19_000_100, 19_000_100),
+        (19_000_050, 19_000_100), // never goes back down, e.g a re-ingested canonical block
+    ];
+
+    for (stored, expected) in cases {
+        store_tracked_block_number(&app.db_pool, stored)
+            .await
+            .unwrap();
+        assert_eq!(
+            tracked_block_number(&app.db_pool).await.unwrap(),
+            Some(expected),
+            "Failed for {}",
+            stored
+        );
+    }
+
+    teardown_test_db(app).await.unwrap();
+}
@@ -0,0 +1,143 @@
+use std::{collections::HashMap, str::FromStr};
+
+use alloy::{
+    primitives::{Address, B256},
+    rpc::types::Header,
+};
+use serial_test::serial;
+use sqlx::{types::BigDecimal, PgPool};
+use tx_fees::{
+    configs::PoolConfig,
+    helpers::{store_block, store_blocks, store_tx, BlockRecord, TxGas, TxRecord},
+    price_providers::Quote,
+};
+
+use crate::utils::{spawn_test_server, teardown_test_db};
+
+fn header(number: u64, extra: u8) -> Header {
+    Header::new(alloy::consensus::Header {
+        number,
+        timestamp: 1706826922 + number,
+        base_fee_per_gas: Some(19000000000),
+        extra_data: vec![extra].into(),
+        ..Default::default()
+    })
+}
+
+fn tx(hash: B256) -> TxGas {
+    TxGas {
+        hash: hash.to_string(),
+        effective_gas_price: 20000000000,
+        gas_used: 214285,
+        blob_gas_used: None,
+        blob_gas_price: None,
+    }
+}
+
+fn pool() -> PoolConfig {
+    PoolConfig {
+        address: Address::from_str("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap(),
+        price_pair: "ETHUSDT".to_string(),
+    }
+}
+
+fn fee() -> BigDecimal {
+    BigDecimal::from_str("15.00092305172864").unwrap()
+}
+
+fn record(header: &Header, prices: &HashMap<String, Quote>, txs: &[TxGas]) -> BlockRecord {
+    BlockRecord {
+        header: header.clone(),
+        prices: prices.clone(),
+        txs: txs
+            .iter()
+            .map(|tx| TxRecord {
+                pool: pool(),
+                tx: tx.clone(),
+                fee: fee(),
+            })
+            .collect(),
+    }
+}
+
+async fn counts(db_pool: &PgPool) -> (i64, i64, i64) {
+    let blocks = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM blocks"#)
+        .fetch_one(db_pool)
+        .await
+        .unwrap();
+    let block_prices = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM block_prices"#)
+        .fetch_one(db_pool)
+        .await
+        .unwrap();
+    let txs = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM txs"#)
+        .fetch_one(db_pool)
+        .await
+        .unwrap();
+    (blocks, block_prices, txs)
+}
+
+#[tokio::test]
+#[serial]
+async fn test_store_blocks_idempotent() {
+    let app = spawn_test_server().await;
+    let prices = HashMap::from([
+        (
+            "ETHUSDT".to_string(),
+            Quote::new(BigDecimal::from_str("3500.12").unwrap(), "binance"),
+        ),
+        (
+            "ETHEUR".to_string(),
+            Quote::new(BigDecimal::from_str("3200.5").unwrap(), "binance"),
+        ),
+    ]);
+    let (first, second) = (header(17000000, 0), header(17000001, 0));
+    let (first_tx, second_tx) = (tx(B256::repeat_byte(1)), tx(B256::repeat_byte(2)));
+
+    // the tracker stores the first block & tx
+    store_block(&app.db_pool, &first, "ETHUSDT", &prices)
+        .await
+        .unwrap();
+    store_tx(
+        &app.db_pool,
+        &first.hash.to_string(),
+        &pool(),
+        &first_tx,
+        first.base_fee_per_gas,
+        &fee(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(counts(&app.db_pool).await, (1, 2, 1));
+
+    // a job overlapping it (and rerun) stores the rest
+    let records = vec![
+        record(&first, &prices, std::slice::from_ref(&first_tx)),
+        record(&second, &prices, &[second_tx]),
+    ];
+    for _ in 0..2 {
+        store_blocks(&app.db_pool, "ETHUSDT", &records)
+            .await
+            .unwrap();
+        assert_eq!(counts(&app.db_pool).await, (2, 4, 2));
+    }
+
+    // a tx that got reorged into another block moves along with it
+    let reorged = header(17000000, 1);
+    store_blocks(
+        &app.db_pool,
+        "ETHUSDT",
+        &[record(&reorged, &prices, std::slice::from_ref(&first_tx))],
+    )
+    .await
+    .unwrap();
+    assert_eq!(counts(&app.db_pool).await, (3, 6, 2));
+
+    let block_hash =
+        sqlx::query_scalar!("SELECT block_hash FROM txs WHERE hash = $1", first_tx.hash)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(block_hash, reorged.hash.to_string());
+
+    teardown_test_db(app).await.unwrap();
+}
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use eyre::eyre;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use reqwest::Client;
+use serde_json::json;
+use serial_test::serial;
+use sqlx::postgres::PgPoolOptions;
+use tx_fees::{
+    components::job_executor::{
+        queue::{
+            heartbeat, heartbeat_key, processing_key, promote_due, reap, JobQueue, DELAYED, QUEUE,
+            WORKERS,
+        },
+        run_next,
+    },
+    configs::RetryConfig,
+};
+
+use crate::utils::{spawn_test_server, teardown_test_db, TestServer};
+
+async fn clear_queue(con: &mut MultiplexedConnection) {
+    let _: () = con.del(&[QUEUE, DELAYED, WORKERS]).await.unwrap();
+}
+
+async fn create_job(app: &TestServer) -> i64 {
+    Client::new()
+        .post(format!("{}/v1/jobs", &app.address))
+        .json(&json!({
+            "start_time": 1514764800,
+            "end_time": 1514851200
+        }))
+        .send()
+        .await
+        .expect("Failed to create job")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse JSON")["job_id"]
+        .as_i64()
+        .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn test_reap_dead_worker() {
+    let app = spawn_test_server().await;
+    let mut con = app
+        .redis_client
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+    clear_queue(&mut con).await;
+
+    let job_id = create_job(&app).await;
+    let mut dead = JobQueue::new(app.redis_client.clone(), Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert_eq!(dead.next().await.unwrap(), Some(job_id.to_string()));
+    sqlx::query!(
+        "UPDATE batch_jobs SET status = 'processing' WHERE id = $1",
+        job_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // the worker is alive, its job is left alone
+    assert_eq!(reap(&mut con, &app.db_pool).await.unwrap(), 0);
+
+    // the worker dies mid-job, its heartbeat expires
+    let worker = dead.worker().to_string();
+    drop(dead);
+    let _: () = con.del(heartbeat_key(&worker)).await.unwrap();
+
+    assert_eq!(reap(&mut con, &app.db_pool).await.unwrap(), 1);
+    let queued: Vec<i64> = con.lrange(QUEUE, 0, -1).await.unwrap();
+    assert_eq!(queued, vec![job_id]);
+    let processing: Vec<i64> = con.lrange(processing_key(&worker), 0, -1).await.unwrap();
+    assert!(processing.is_empty());
+    let workers: Vec<String> = con.smembers(WORKERS).await.unwrap();
+    assert!(!workers.contains(&worker));
+
+    // a worker that was only late with its heartbeat registers again on the next one
+    heartbeat(&mut con, &worker, Duration::from_secs(1))
+        .await
+        .unwrap();
+    let workers: Vec<String> = con.smembers(WORKERS).await.unwrap();
+    assert!(workers.contains(&worker));
+    let _: () = con.del(heartbeat_key(&worker)).await.unwrap();
+
+    let status = sqlx::query_scalar!("SELECT status FROM batch_jobs WHERE id = $1", job_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "pending");
+
+    clear_queue(&mut con).await;
+    teardown_test_db(app).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_retry_when_due() {
+    let app = spawn_test_server().await;
+    let mut con = app
+        .redis_client
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+    clear_queue(&mut con).await;
+
+    let mut queue = JobQueue::new(app.redis_client.clone(), Duration::from_secs(30))
+        .await
+        .unwrap();
+    queue.retry(1, Duration::ZERO).await.unwrap();
+    queue.retry(2, Duration::from_secs(3600)).await.unwrap();
+
+    // only the due retry goes back to the queue
+    assert_eq!(promote_due(&mut con).await.unwrap(), 1);
+    assert_eq!(promote_due(&mut con).await.unwrap(), 0);
+    let queued: Vec<i64> = con.lrange(QUEUE, 0, -1).await.unwrap();
+    assert_eq!(queued, vec![1]);
+
+    let _: () = con.del(heartbeat_key(queue.worker())).await.unwrap();
+    clear_queue(&mut con).await;
+    teardown_test_db(app).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_failing_job_keeps_executor_running() {
+    let app = spawn_test_server().await;
+    let mut con = app
+        .redis_client
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+    clear_queue(&mut con).await;
+
+    let retry = RetryConfig {
+        max_retries: 1,
+        backoff: Duration::from_secs(30),
+    };
+    let (retried, failed, completed) = (
+        create_job(&app).await,
+        create_job(&app).await,
+        create_job(&app).await,
+    );
+    // already went through its retry
+    sqlx::query!("UPDATE batch_jobs SET attempts = 2 WHERE id = $1", failed)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let mut queue = JobQueue::new(app.redis_client.clone(), Duration::from_secs(30))
+        .await
+        .unwrap();
+    let cases = vec![
+        // (job, execution, expected status)
+        (retried, Err(eyre!("RPC unavailable")), "pending"),
+        (failed, Err(eyre!("RPC unavailable")), "failed"),
+        (completed, Ok(()), "pending"), // as left by the execution
+    ];
+
+    for (job_id, execution, expected) in cases {
+        run_next(&mut queue, &app.db_pool, &retry, |picked| async move {
+            assert_eq!(picked, job_id);
+            execution
+        })
+        .await
+        .unwrap_or_else(|err| panic!("Failed for {}: {}", job_id, err));
+
+        let status = sqlx::query_scalar!("SELECT status FROM batch_jobs WHERE id = $1", job_id)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(status, expected, "Failed for {}", job_id);
+    }
+
+    // every job got acked, only the one with retries left waits for its retry
+    let processing: Vec<i64> = con
+        .lrange(processing_key(queue.worker()), 0, -1)
+        .await
+        .unwrap();
+    assert!(processing.is_empty());
+    let delayed: Vec<i64> = con.zrange(DELAYED, 0, -1).await.unwrap();
+    assert_eq!(delayed, vec![retried]);
+
+    // a failure that can't be recorded leaves the job to be reaped once the executor is gone
+    let unrecorded = create_job(&app).await;
+    let closed_pool =
+        PgPoolOptions::new().connect_lazy_with((*app.db_pool.connect_options()).clone());
+    closed_pool.close().await;
+    let result = run_next(&mut queue, &closed_pool, &retry, |_| async {
+        Err(eyre!("RPC unavailable"))
+    })
+    .await;
+    assert!(result.is_err());
+    let processing: Vec<i64> = con
+        .lrange(processing_key(queue.worker()), 0, -1)
+        .await
+        .unwrap();
+    assert_eq!(processing, vec![unrecorded]);
+
+    let _: () = con.del(heartbeat_key(queue.worker())).await.unwrap();
+    assert_eq!(reap(&mut con, &app.db_pool).await.unwrap(), 1);
+    let queued: Vec<i64> = con.lrange(QUEUE, 0, -1).await.unwrap();
+    assert_eq!(queued, vec![unrecorded]);
+
+    clear_queue(&mut con).await;
+    teardown_test_db(app).await.unwrap();
+}
@@ -1,2 +1,6 @@
 pub mod api;
+pub mod fee_tracker;
+pub mod helpers;
+pub mod job_queue;
+pub mod price_providers;
 pub mod utils;
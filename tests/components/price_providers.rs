@@ -0,0 +1,95 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use serial_test::serial;
+use sqlx::types::BigDecimal;
+use tx_fees::price_providers::{Cached, PriceSource, PriceStrategy, PricedBlock, Quote, Staleness};
+
+use crate::utils::{spawn_test_server, teardown_test_db};
+
+/// Answers with the number of times it got asked for a price
+#[derive(Debug, Default)]
+struct Counting {
+    calls: AtomicUsize,
+}
+
+#[async_trait]
+impl PriceSource for Counting {
+    fn name(&self) -> String {
+        "counting".to_string()
+    }
+
+    async fn get_price(&self, _pair: &str, _block: PricedBlock) -> eyre::Result<Quote> {
+        let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        Ok(Quote::new(BigDecimal::from(calls as u64), &self.name()))
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_cached_price_source() {
+    let app = spawn_test_server().await;
+    let source = Arc::new(Counting::default());
+    let staleness = |strategy| Staleness {
+        strategy,
+        max_gap: 60,
+    };
+    let cached = Cached::new(
+        source.clone(),
+        app.db_pool.clone(),
+        staleness(PriceStrategy::Nearest),
+    );
+
+    let block = |timestamp| PricedBlock {
+        number: 17000000,
+        timestamp,
+    };
+
+    // historical prices are only fetched once per pair & timestamp
+    let cases = vec![
+        ("ETHUSDT", Some(1706826922), 1, 1),
+        ("ETHUSDT", Some(1706826922), 1, 1),
+        ("ETHUSDC", Some(1706826922), 2, 2),
+        ("ETHUSDT", Some(1706826923), 3, 3),
+        ("ETHUSDC", Some(1706826922), 2, 3),
+        // spot prices aren't cached
+        ("ETHUSDT", None, 4, 4),
+        ("ETHUSDT", None, 5, 5),
+    ];
+
+    for (pair, timestamp, expected_price, expected_calls) in cases {
+        let quote = cached.get_price(pair, block(timestamp)).await.unwrap();
+        assert_eq!(
+            quote,
+            Quote::new(BigDecimal::from(expected_price), "counting"),
+            "Failed for {} at {:?}",
+            pair,
+            timestamp
+        );
+        assert_eq!(source.calls.load(Ordering::SeqCst), expected_calls);
+    }
+
+    // another staleness doesn't get the prices resolved with the first one
+    let interpolated = Cached::new(
+        source.clone(),
+        app.db_pool.clone(),
+        staleness(PriceStrategy::Interpolate),
+    );
+    let quote = interpolated
+        .get_price("ETHUSDT", block(Some(1706826922)))
+        .await
+        .unwrap();
+    assert_eq!(quote, Quote::new(BigDecimal::from(6), "counting"));
+    assert_eq!(source.calls.load(Ordering::SeqCst), 6);
+
+    let cached_prices: i64 = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM prices"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(cached_prices, 4);
+
+    teardown_test_db(app).await.unwrap();
+}
@@ -1,7 +1,11 @@
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use uuid::Uuid;
 
-use tx_fees::{components::api::ServerApp, configs::ServerConfig};
+use tx_fees::{
+    components::api::ServerApp,
+    configs::{ServerConfig, SupportedPairs},
+    price_providers::PriceSourceKind,
+};
 
 pub async fn setup_test_db() -> std::result::Result<(PgPool, String), sqlx::Error> {
     let db_url = std::env::var("TEST_DATABASE_URL")
@@ -72,6 +76,11 @@ pub async fn spawn_test_server() -> TestServer {
     let server_app = ServerApp::build(ServerConfig::new(
         db_pool.clone(),
         redis_url.clone(),
+        // the tracked pools' on-chain prices only, i.e just the configured pairs
+        SupportedPairs::new(
+            vec![PriceSourceKind::UniswapV3],
+            vec!["ETHUSDT".to_string(), "ETHUSDC".to_string()],
+        ),
         "localhost".to_string(),
         0,
     ))
@@ -79,7 +88,7 @@ pub async fn spawn_test_server() -> TestServer {
     .expect("Failed to build the Server application.");
 
     let server_app_port = server_app.port();
-    let _ = tokio::spawn(async move { server_app.run_until_stopped().await });
+    tokio::spawn(async move { server_app.run_until_stopped().await });
 
     TestServer {
         address: format!("http://localhost:{}", server_app_port),